@@ -0,0 +1,105 @@
+use std::{collections::VecDeque, io::{self, BufRead, Write}};
+
+mod editor;
+use editor::Editor;
+
+// Where the game reads commands from and writes its text to.
+// Rooms only ever talk to a Console, so the same game can run in a terminal or off a script.
+pub trait Console {
+    // Read one line of input, or None once there's nothing left to read.
+    fn read_line(&mut self) -> Option<String>;
+
+    // Write text as-is.
+    fn print(&mut self, text: &str);
+
+    // Write text followed by a newline.
+    fn println(&mut self, text: &str) {
+        self.print(text);
+        self.print("\n");
+    }
+
+    // Verbs and names tab completion should offer for the next line read. Consoles that
+    // can't complete anything ignore them.
+    fn set_completions(&mut self, _verbs: Vec<String>, _names: Vec<String>) {}
+}
+
+// The interactive console: stdin and stdout.
+// When both are a terminal, lines are read with a line editor that has history and tab
+// completion; otherwise stdin is read a line at a time as-is.
+pub struct Terminal {
+    editor: Option<Editor>,
+    // Whatever's been printed since the last newline, so the editor can redraw the prompt.
+    prompt: String,
+}
+
+impl Terminal {
+    pub fn new() -> Terminal {
+        Terminal { editor: Editor::new(), prompt: String::new() }
+    }
+}
+
+impl Console for Terminal {
+    fn read_line(&mut self) -> Option<String> {
+        if let Some(editor) = &mut self.editor {
+            match editor.read_line(&self.prompt) {
+                Ok(line) => {
+                    self.prompt.clear();
+                    return line;
+                }
+                // The terminal won't let the editor drive it, so do without from here on.
+                Err(()) => self.editor = None,
+            }
+        }
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+
+    // Panics if stdout().flush() fails, for some weird reason.
+    fn print(&mut self, text: &str) {
+        print!("{}", text);
+        io::stdout().flush().expect("");
+        match text.rsplit_once('\n') {
+            Some((_, rest)) => self.prompt = String::from(rest),
+            None => self.prompt += text,
+        }
+    }
+
+    fn set_completions(&mut self, verbs: Vec<String>, names: Vec<String>) {
+        if let Some(editor) = &mut self.editor {
+            editor.set_completions(verbs, names);
+        }
+    }
+}
+
+// Feeds the game a fixed list of commands and records everything it prints,
+// with each command echoed after its prompt like it would appear in a terminal.
+pub struct Scripted {
+    commands: VecDeque<String>,
+    transcript: String,
+}
+
+impl Scripted {
+    pub fn new<I: IntoIterator<Item = String>>(commands: I) -> Scripted {
+        Scripted { commands: commands.into_iter().collect(), transcript: String::new() }
+    }
+
+    pub fn transcript(&self) -> &str {
+        &self.transcript
+    }
+}
+
+impl Console for Scripted {
+    fn read_line(&mut self) -> Option<String> {
+        let line = self.commands.pop_front()?;
+        self.transcript += &line;
+        self.transcript += "\n";
+        Some(line)
+    }
+
+    fn print(&mut self, text: &str) {
+        self.transcript += text;
+    }
+}
@@ -0,0 +1,301 @@
+use std::{fs, io::{self, IsTerminal, Read, Write}, path::PathBuf, process::{Command, Stdio}};
+
+// A small line editor for the interactive console.
+// The terminal is switched out of canonical mode with `stty` for as long as a line is being
+// typed, and put back the way it was afterwards, so anything else reading stdin in between
+// sees a normal terminal. Supported keys:
+//
+//   left/right, ctrl-b/ctrl-f   move the cursor      up/down        previous/next history entry
+//   home/end, ctrl-a/ctrl-e     start/end of line    tab            complete the word at the cursor
+//   backspace, delete, ctrl-d   delete a character   ctrl-u/ctrl-k  delete to start/end of line
+//   ctrl-w                      delete a word        ctrl-c         abandon the line
+//
+// ctrl-d on an empty line ends input, like it would at a plain prompt.
+
+// How many lines of history are kept, both in memory and on disk.
+const HISTORY_LEN: usize = 500;
+
+pub struct Editor {
+    // The terminal settings to put back once a line has been read, as printed by `stty -g`.
+    saved: String,
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
+    // What tab completes to: verbs at the start of a command, names anywhere else.
+    verbs: Vec<String>,
+    names: Vec<String>,
+}
+
+// Where the history is kept between sessions.
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".encrusted_history"))
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(|s| String::from(s.trim()))
+}
+
+fn write(text: &str) {
+    let mut out = io::stdout();
+    // Nothing sensible to do if the terminal has gone away.
+    let _ = out.write_all(text.as_bytes()).and_then(|_| out.flush());
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = [0];
+    match io::stdin().read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+// Read the rest of a UTF-8 character whose first byte is `first`.
+fn read_char(first: u8) -> Option<char> {
+    let len = match first {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.push(read_byte()?);
+    }
+    std::str::from_utf8(&bytes).ok()?.chars().next()
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillStart,
+    KillEnd,
+    KillWord,
+    Interrupt,
+    EndOfInput,
+    Ignored,
+}
+
+fn read_key() -> Option<Key> {
+    let key = match read_byte()? {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfInput,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillStart,
+        0x17 => Key::KillWord,
+        0x1b => read_escape()?,
+        b if b < 0x20 => Key::Ignored,
+        b => read_char(b).map_or(Key::Ignored, Key::Char),
+    };
+    Some(key)
+}
+
+// Arrow keys and friends arrive as `ESC [ <params> <letter>` or `ESC O <letter>`.
+fn read_escape() -> Option<Key> {
+    let mut params = String::new();
+    let last = match read_byte()? {
+        b'[' | b'O' => loop {
+            match read_byte()? {
+                b @ (b'0'..=b'9' | b';') => params.push(char::from(b)),
+                b => break b,
+            }
+        },
+        _ => return Some(Key::Ignored),
+    };
+    let key = match (last, params.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', "1" | "7") => Key::Home,
+        (b'F', _) | (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Ignored,
+    };
+    Some(key)
+}
+
+impl Editor {
+    // An editor for stdin, if it and stdout are a terminal that `stty` can drive.
+    pub fn new() -> Option<Editor> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return None;
+        }
+        let saved = stty(&["-g"])?;
+        let history_file = history_file();
+        let history = history_file.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|source| source.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Some(Editor { saved, history, history_file, verbs: vec![], names: vec![] })
+    }
+
+    pub fn set_completions(&mut self, verbs: Vec<String>, names: Vec<String>) {
+        self.verbs = verbs;
+        self.names = names;
+    }
+
+    // Read a line, redrawing it after `prompt` as it's edited.
+    // Returns Ok(None) at the end of input, and Err if the terminal can't be put into raw mode,
+    // in which case nothing has been read.
+    pub fn read_line(&mut self, prompt: &str) -> Result<Option<String>, ()> {
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"]).ok_or(())?;
+        let line = self.edit(prompt);
+        stty(&[&self.saved]);
+        write("\n");
+
+        let Some(line) = line else {
+            return Ok(None);
+        };
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            if self.history.len() > HISTORY_LEN {
+                self.history.drain(..self.history.len() - HISTORY_LEN);
+            }
+            if let Some(path) = &self.history_file {
+                // Losing history isn't worth interrupting the game over.
+                let _ = fs::write(path, self.history.join("\n") + "\n");
+            }
+        }
+        Ok(Some(line))
+    }
+
+    fn edit(&self, prompt: &str) -> Option<String> {
+        let mut buf: Vec<char> = vec![];
+        let mut cursor = 0;
+        // Where we are in the history; history.len() is the line being typed.
+        let mut entry = self.history.len();
+        let mut draft: Vec<char> = vec![];
+
+        loop {
+            match read_key()? {
+                Key::Enter => return Some(buf.into_iter().collect()),
+                Key::EndOfInput if buf.is_empty() => return None,
+                Key::Interrupt => {
+                    write("^C");
+                    return Some(String::new());
+                }
+                Key::Char(c) => {
+                    buf.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    buf.remove(cursor);
+                }
+                Key::Delete | Key::EndOfInput if cursor < buf.len() => {
+                    buf.remove(cursor);
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(buf.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = buf.len(),
+                Key::KillStart => {
+                    buf.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::KillEnd => buf.truncate(cursor),
+                Key::KillWord => {
+                    let mut start = cursor;
+                    while start > 0 && buf[start - 1] == ' ' {
+                        start -= 1;
+                    }
+                    while start > 0 && buf[start - 1] != ' ' {
+                        start -= 1;
+                    }
+                    buf.drain(start..cursor);
+                    cursor = start;
+                }
+                Key::Up if entry > 0 => {
+                    if entry == self.history.len() {
+                        draft = buf.clone();
+                    }
+                    entry -= 1;
+                    buf = self.history[entry].chars().collect();
+                    cursor = buf.len();
+                }
+                Key::Down if entry < self.history.len() => {
+                    entry += 1;
+                    buf = match self.history.get(entry) {
+                        Some(line) => line.chars().collect(),
+                        None => draft.clone(),
+                    };
+                    cursor = buf.len();
+                }
+                Key::Tab => {
+                    let matches = self.complete(&mut buf, &mut cursor);
+                    if matches.len() > 1 {
+                        write(&format!("\r\n{}\r\n", matches.join("  ")));
+                    }
+                }
+                _ => {}
+            }
+
+            // Redraw the whole line and put the cursor back where it belongs.
+            let text: String = buf.iter().collect();
+            write(&format!("\r{}{}\x1b[K", prompt, text));
+            if cursor < buf.len() {
+                write(&format!("\x1b[{}D", buf.len() - cursor));
+            }
+        }
+    }
+
+    // Complete the text before the cursor against the verbs or names. Names can be more
+    // than one word, so the longest run of words before the cursor that starts any of them
+    // is what gets completed. Returns every match, so the caller can list them if there's
+    // more than one.
+    fn complete(&self, buf: &mut Vec<char>, cursor: &mut usize) -> Vec<String> {
+        let before: String = buf[..*cursor].iter().collect::<String>().to_lowercase();
+        let starts = (0..before.len()).filter(|&i| before.is_char_boundary(i) && (i == 0 || before[..i].ends_with(' ')));
+        for start in starts {
+            let typed = &before[start..];
+            if typed.trim().is_empty() {
+                continue;
+            }
+            let previous = before[..start].trim_end();
+            let command_start = previous.is_empty() || previous.ends_with(['.', ';']) || previous == "then" || previous.ends_with(" then");
+            let words = if command_start { &self.verbs } else { &self.names };
+            let mut matches: Vec<String> = words.iter().filter(|w| w.starts_with(typed)).cloned().collect();
+            if matches.is_empty() {
+                continue;
+            }
+            matches.sort();
+            matches.dedup();
+
+            // Extend as far as every match agrees, finishing the word if there's only one.
+            let mut common = matches[0].clone();
+            for m in &matches[1..] {
+                let len = common.chars().zip(m.chars()).take_while(|(a, b)| a == b).count();
+                common = common.chars().take(len).collect();
+            }
+            if matches.len() == 1 {
+                common.push(' ');
+            }
+            let start = before[..start].chars().count();
+            buf.splice(start..*cursor, common.chars());
+            *cursor = start + common.chars().count();
+            return matches;
+        }
+        vec![]
+    }
+}
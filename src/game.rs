@@ -0,0 +1,295 @@
+use std::{mem, collections::{HashMap, HashSet, VecDeque}};
+use crate::{console::Console, get_user_input, parser::Referents, save, world::{Detail, World}, Flags, Inventory, ParsedInput};
+
+// What a room handler wants the game loop to do after a command.
+pub enum Transition {
+    Stay,
+    // The command didn't work. Stay put, and drop anything chained after it.
+    Failed,
+    GoTo(String),
+    Quit,
+    Error(String),
+}
+
+// How a game ended.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Quit,
+    Error(String),
+}
+
+// Everything about a session that changes as it's played. This is what gets saved, and
+// what `undo` takes snapshots of.
+#[derive(Clone, PartialEq)]
+pub struct State {
+    pub room: String,
+    pub inv: Inventory,
+    pub flags: Flags,
+    // Items lying in each room or sitting in each container, keyed by room or container id.
+    pub contents: HashMap<String, Inventory>,
+    // Rooms the player has been in and left again.
+    pub visited: HashSet<String>,
+}
+
+impl State {
+    // Every item the player has or that's somewhere in the world.
+    fn items(&self) -> impl Iterator<Item = &String> {
+        self.inv.items.iter().chain(self.contents.values().flat_map(|place| &place.items))
+    }
+}
+
+// How much of a room's description is shown when the player walks into it. `look` always
+// shows all of it.
+#[derive(Clone, Copy)]
+enum Verbosity {
+    // Everything, every time.
+    Verbose,
+    // Everything the first time, and just the name and what's in it after that.
+    Brief,
+    // Only ever the name.
+    Superbrief,
+}
+
+// A running session: the loaded world plus everything the player has changed in it.
+pub struct Game {
+    world: World,
+    state: State,
+    // What "it" and "them" currently mean.
+    referents: Referents,
+    // Commands still to run from the last line typed.
+    queue: VecDeque<ParsedInput>,
+    // The last command that worked, for `again`.
+    last: Option<ParsedInput>,
+    // Snapshots from before each command that changed something, most recent last.
+    undo: Vec<State>,
+    undo_depth: usize,
+    verbosity: Verbosity,
+    // Whether the player has just arrived somewhere and hasn't been shown it yet.
+    arrived: bool,
+}
+
+// How many commands `undo` can take back unless told otherwise.
+const UNDO_DEPTH: usize = 10;
+
+impl Game {
+    pub fn new(world: World) -> Game {
+        let state = State {
+            room: world.start.clone(),
+            inv: Inventory::new(),
+            flags: world.initial_flags(),
+            contents: world.initial_contents(),
+            visited: HashSet::new(),
+        };
+        Game {
+            world,
+            state,
+            referents: Referents::default(),
+            queue: VecDeque::new(),
+            last: None,
+            undo: vec![],
+            undo_depth: UNDO_DEPTH,
+            verbosity: Verbosity::Brief,
+            arrived: true,
+        }
+    }
+
+    // How many commands `undo` can take back. 0 turns undo off.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        self.trim_undo();
+    }
+
+    fn trim_undo(&mut self) {
+        if self.undo.len() > self.undo_depth {
+            self.undo.drain(..self.undo.len() - self.undo_depth);
+        }
+    }
+
+    // Run the game loop until the player quits or something goes wrong.
+    pub fn run(&mut self, console: &mut dyn Console) -> Outcome {
+        loop {
+            match self.step(console) {
+                Transition::Stay => {}
+                Transition::Failed => self.queue.clear(),
+                Transition::GoTo(room) => {
+                    let left = mem::replace(&mut self.state.room, room);
+                    self.state.visited.insert(left);
+                    self.arrived = true;
+                }
+                Transition::Quit => return Outcome::Quit,
+                Transition::Error(e) => return Outcome::Error(e),
+            }
+        }
+    }
+
+    // Describe the room if the player has just arrived in it, then carry out the next queued
+    // command, reading another line once the queue runs dry.
+    fn step(&mut self, console: &mut dyn Console) -> Transition {
+        if !self.world.rooms.contains_key(&self.state.room) {
+            return Transition::Error(format!("Attempting to access a room (`{}`) that doesn't exist.", self.state.room));
+        }
+        if self.arrived {
+            let detail = match self.verbosity {
+                Verbosity::Verbose => Detail::Full,
+                Verbosity::Brief if !self.state.visited.contains(&self.state.room) => Detail::Full,
+                Verbosity::Brief => Detail::Short,
+                Verbosity::Superbrief => Detail::Name,
+            };
+            self.world.describe(&self.state, detail, console);
+            self.arrived = false;
+        }
+        if self.queue.is_empty() {
+            let (verbs, names) = self.world.completions(&self.state);
+            console.set_completions(verbs, names);
+            match get_user_input(console) {
+                Ok(commands) => self.queue.extend(commands),
+                Err(_) => return Transition::Quit,
+            }
+        }
+
+        let Some(input) = self.queue.pop_front() else {
+            return Transition::Stay;
+        };
+        let input = match input {
+            ParsedInput::Again => match &self.last {
+                Some(last) => last.clone(),
+                None => {
+                    console.println("There's nothing to repeat.");
+                    return Transition::Failed;
+                }
+            },
+            input => match self.referents.substitute(input) {
+                Ok(input) => input,
+                Err(e) => {
+                    console.println(&e);
+                    return Transition::Failed;
+                }
+            },
+        };
+
+        // Meta-commands work the same everywhere; anything else is up to the room.
+        let before = self.state.clone();
+        let undoing = matches!(input, ParsedInput::Undo);
+        let transition = match self.meta_command(&input, console) {
+            Some(transition) => transition,
+            None => self.world.handle(&mut self.state, &input, &mut self.referents, &mut self.queue, console),
+        };
+        if !matches!(transition, Transition::Failed) {
+            self.last = Some(input);
+        }
+
+        // Moving counts as a change too, even though the loop is what updates the room.
+        let moved = matches!(&transition, Transition::GoTo(room) if *room != before.room);
+        if (moved || self.state != before) && !undoing {
+            self.undo.push(before);
+            self.trim_undo();
+        }
+        transition
+    }
+
+    // Handle commands that don't depend on which room the player is in.
+    // Returns None if the input isn't a meta-command.
+    fn meta_command(&mut self, input: &ParsedInput, console: &mut dyn Console) -> Option<Transition> {
+        match input {
+            ParsedInput::Quit => return Some(Transition::Quit),
+            ParsedInput::Help => console.println(HELP),
+            ParsedInput::Inv => console.println(&self.world.show_inventory(&self.state.inv, &self.state.contents)),
+            // A debugging aid: every flag and variable, and what it's set to.
+            ParsedInput::Flags => {
+                let mut vars: Vec<_> = self.state.flags.flags.iter().collect();
+                vars.sort_by(|a, b| a.0.cmp(b.0));
+                for (name, val) in vars {
+                    console.println(&format!("{} = {}", name, val));
+                }
+            }
+            ParsedInput::Undo => match self.undo.pop() {
+                Some(state) => {
+                    self.state = state;
+                    self.arrived = true;
+                    console.println("Undone.");
+                }
+                None => {
+                    console.println("There's nothing to undo.");
+                    return Some(Transition::Failed);
+                }
+            },
+            ParsedInput::Look(thing) if thing.is_empty() => self.world.describe(&self.state, Detail::Full, console),
+            ParsedInput::Verbose => {
+                self.verbosity = Verbosity::Verbose;
+                console.println("Verbose mode: rooms are described in full every time you enter them.");
+            }
+            ParsedInput::Brief => {
+                self.verbosity = Verbosity::Brief;
+                console.println("Brief mode: rooms are described in full the first time you enter them, and briefly after that.");
+            }
+            ParsedInput::Superbrief => {
+                self.verbosity = Verbosity::Superbrief;
+                console.println("Superbrief mode: only the names of rooms are shown. Use `look` to see the rest.");
+            }
+            ParsedInput::Save(slot) => match save::save(slot, &self.state) {
+                Ok(()) => console.println(&format!("Game saved to slot `{}`.", slot)),
+                Err(e) => {
+                    console.println(&e);
+                    return Some(Transition::Failed);
+                }
+            },
+            ParsedInput::Load(slot) => match save::load(slot) {
+                Ok(state) if !self.world.rooms.contains_key(&state.room) => {
+                    console.println(&format!("Slot `{}` is in room `{}`, which this world doesn't have.", slot, state.room));
+                    return Some(Transition::Failed);
+                }
+                Ok(mut state) => {
+                    if let Some(item) = state.items().find(|item| !self.world.has_item(item)) {
+                        console.println(&format!("Slot `{}` has an item (`{}`) this world doesn't have.", slot, item));
+                        return Some(Transition::Failed);
+                    }
+                    if let Some(var) = state.flags.flags.keys().find(|var| !self.world.declares(var)) {
+                        console.println(&format!("Slot `{}` has a flag (`{}`) this world doesn't declare.", slot, var));
+                        return Some(Transition::Failed);
+                    }
+                    // Places the save doesn't mention are empty, not back to how they started.
+                    for place in self.world.places() {
+                        state.contents.entry(place.clone()).or_insert_with(Inventory::new);
+                    }
+                    self.state = state;
+                    self.arrived = true;
+                    console.println(&format!("Game loaded from slot `{}`.", slot));
+                }
+                Err(e) => {
+                    console.println(&e);
+                    return Some(Transition::Failed);
+                }
+            },
+            _ => return None,
+        }
+        Some(Transition::Stay)
+    }
+}
+
+const HELP: &str = "\
+Moving around:  [go] north, south, east, west, northeast, northwest, southeast, southwest,
+                up, down, in, out (or n, s, e, w, ne, nw, se, sw, u, d)
+Doing things:   look [at <thing>], get <item>|all, drop <item>|all [except <item>], use <item> [on <thing>], talk to <someone>
+                open <thing>, close <thing>, lock <thing> [with <key>], unlock <thing> [with <key>]
+Meta-commands:  inv, again (or g), undo, save <slot>, load <slot>, help, quit
+                verbose, brief, superbrief: how much to describe rooms on arrival
+Debugging:      flags
+Several commands can go on one line, separated by `.` or `then`: get key. n. use key on chest";
+
+#[cfg(test)]
+mod tests {
+    use crate::{console::Scripted, world::World};
+    use super::{Game, Outcome};
+
+    // A walkthrough of the test world, from the test room to the closet and back, has to play
+    // out exactly as recorded. After a deliberate change to the game's text, regenerate the
+    // transcript with `cargo run -- --script tests/walkthrough/commands.txt`.
+    #[test]
+    fn walkthrough() {
+        let world = World::load(concat!(env!("CARGO_MANIFEST_DIR"), "/worlds/test.world")).unwrap();
+        let commands = include_str!("../tests/walkthrough/commands.txt").lines().map(String::from);
+        let mut console = Scripted::new(commands);
+        assert_eq!(Game::new(world).run(&mut console), Outcome::Quit);
+        assert_eq!(console.transcript(), include_str!("../tests/walkthrough/transcript.txt"));
+    }
+}
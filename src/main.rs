@@ -1,25 +1,58 @@
-use std::{fmt, io::Write, process::exit, collections::HashMap};
-use regex::Regex;
+use std::{cmp::Ordering, fmt, fs, process::ExitCode, collections::HashMap};
+
+mod console;
+mod game;
+mod parser;
+mod save;
+mod world;
+use console::{Console, Scripted, Terminal};
+use game::{Game, Outcome};
+use parser::{parse_input, split_line};
+use world::World;
 
 // Utility types.
 // These help organize data into something a little easier to grok.
+#[derive(Clone, Debug, PartialEq)]
 enum ParsedInput {
     // Meta-commands
     Quit,
     Inv,
+    Help,
+    Again,
+    Undo,
+    Flags,
+    Verbose,
+    Brief,
+    Superbrief,
+    Save(String),
+    Load(String),
     // Actions
     Look(String),
     Get(String),
+    GetFrom(String, String),
+    PutIn(String, String),
     Use(String),
     UseOn(String, String),
     Talk(String),
+    Drop(String),
+    Open(String),
+    Close(String),
+    // What to lock or unlock, and what with. The key is empty if the player didn't say.
+    Lock(String, String),
+    Unlock(String, String),
     // Directions
     North,
     South,
     East,
     West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
     Down,
     Up,
+    In,
+    Out,
     // Catch-all
     Other(String)
 }
@@ -31,32 +64,144 @@ impl fmt::Display for ParsedInput {
             // Meta-commands
             ParsedInput::Quit                          => write!(f, "Quit"),
             ParsedInput::Inv                           => write!(f, "Inv"),
+            ParsedInput::Help                          => write!(f, "Help"),
+            ParsedInput::Again                         => write!(f, "Again"),
+            ParsedInput::Undo                          => write!(f, "Undo"),
+            ParsedInput::Flags                         => write!(f, "Flags"),
+            ParsedInput::Verbose                       => write!(f, "Verbose"),
+            ParsedInput::Brief                         => write!(f, "Brief"),
+            ParsedInput::Superbrief                    => write!(f, "Superbrief"),
+            ParsedInput::Save(s)              => write!(f, "Save({})", s),
+            ParsedInput::Load(s)              => write!(f, "Load({})", s),
             // Actions
             ParsedInput::Look(s)              => write!(f, "Look({})", s),
             ParsedInput::Get(s)               => write!(f, "Get({})", s),
+            ParsedInput::GetFrom(s, t)  => write!(f, "GetFrom({}, {})", s, t),
+            ParsedInput::PutIn(s, t)    => write!(f, "PutIn({}, {})", s, t),
             ParsedInput::Use(s)               => write!(f, "Use({})", s),
             ParsedInput::UseOn(s, t) => write!(f, "UseOn({}, {})", s, t),
             ParsedInput::Talk(s)              => write!(f, "Talk({})", s),
+            ParsedInput::Drop(s)              => write!(f, "Drop({})", s),
+            ParsedInput::Open(s)              => write!(f, "Open({})", s),
+            ParsedInput::Close(s)             => write!(f, "Close({})", s),
+            ParsedInput::Lock(s, t)     => write!(f, "Lock({}, {})", s, t),
+            ParsedInput::Unlock(s, t)   => write!(f, "Unlock({}, {})", s, t),
             // Directions
             ParsedInput::North                         => write!(f, "North"),
             ParsedInput::South                         => write!(f, "South"),
             ParsedInput::East                          => write!(f, "East"),
             ParsedInput::West                          => write!(f, "West"),
+            ParsedInput::NorthEast                     => write!(f, "NorthEast"),
+            ParsedInput::NorthWest                     => write!(f, "NorthWest"),
+            ParsedInput::SouthEast                     => write!(f, "SouthEast"),
+            ParsedInput::SouthWest                     => write!(f, "SouthWest"),
             ParsedInput::Down                          => write!(f, "Down"),
             ParsedInput::Up                            => write!(f, "Up"),
+            ParsedInput::In                            => write!(f, "In"),
+            ParsedInput::Out                           => write!(f, "Out"),
             // Catch-all
             ParsedInput::Other(s)             => write!(f, "Other({})", s),
         }
     }
 }
 
+impl ParsedInput {
+    // The name of the direction this input moves in, if it's a movement command.
+    fn direction(&self) -> Option<&'static str> {
+        match self {
+            ParsedInput::North => Some("north"),
+            ParsedInput::South => Some("south"),
+            ParsedInput::East  => Some("east"),
+            ParsedInput::West  => Some("west"),
+            ParsedInput::NorthEast => Some("northeast"),
+            ParsedInput::NorthWest => Some("northwest"),
+            ParsedInput::SouthEast => Some("southeast"),
+            ParsedInput::SouthWest => Some("southwest"),
+            ParsedInput::Down  => Some("down"),
+            ParsedInput::Up    => Some("up"),
+            ParsedInput::In    => Some("in"),
+            ParsedInput::Out   => Some("out"),
+            _ => None,
+        }
+    }
+}
+
 // Wrapper-classes for Vec/HashMap
+// Inventory is used for what the player carries, what's lying around in each room and what's
+// inside each container. It holds item ids; what the items are is up to the world.
+#[derive(Clone, PartialEq)]
 struct Inventory {
-    items: Vec<(String, String)>
+    items: Vec<String>
 }
 
+// The game-state variables: boolean flags, plus counters and strings.
+#[derive(Clone, PartialEq)]
 struct Flags {
-    flags: HashMap<String, bool>
+    flags: HashMap<String, Value>
+}
+
+// The value of a game-state variable.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    // Read a value as written in a world file: `true`, `false`, a whole number, or failing
+    // those, a string. Quotes make it a string regardless, so `"5"` isn't a number.
+    fn parse(s: &str) -> Value {
+        if let Some(s) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            return Value::Str(String::from(s));
+        }
+        match s {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => match s.parse::<i64>() {
+                Ok(n) => Value::Int(n),
+                Err(_) => Value::Str(String::from(s)),
+            },
+        }
+    }
+
+    // What sort of value this is, for error messages.
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "flag",
+            Value::Int(_) => "number",
+            Value::Str(_) => "string",
+        }
+    }
+
+    // What an unset variable counts as when compared against this value.
+    fn empty(&self) -> Value {
+        match self {
+            Value::Bool(_) => Value::Bool(false),
+            Value::Int(_) => Value::Int(0),
+            Value::Str(_) => Value::Str(String::new()),
+        }
+    }
+
+    // Values of different types don't compare at all.
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
 }
 
 // Flags implementation
@@ -67,9 +212,15 @@ impl Flags {
     }
     
     // Check to see if a certain flag is both defined and set to true.
+    // Counters count as set while they're non-zero, and strings while they're non-empty.
     fn is_set(&self, flag: &str) -> bool
     {
-        *self.flags.get(flag).unwrap_or(&false)
+        match self.flags.get(flag) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::Int(n)) => *n != 0,
+            Some(Value::Str(s)) => !s.is_empty(),
+            None => false,
+        }
     }
 
     // Set the given flag to true in the struct.
@@ -80,20 +231,52 @@ impl Flags {
     // Set the given flag to `val` in the struct.
     fn set_as(&mut self, flag: &str, val: bool)
     {
-        self.flags.insert(String::from(flag), val);
+        self.set_value(flag, Value::Bool(val));
     }
 
-    fn print_if(&self, flag: &str, val: bool, msg: &str)
-    {
-        if self.is_set(flag) == val
-        {
-            println!("{}", msg);
+    // The variable's value, if it's ever been set.
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.flags.get(name)
+    }
+
+    fn set_value(&mut self, name: &str, val: Value) {
+        self.flags.insert(String::from(name), val);
+    }
+
+    // The variable as a number. Anything that isn't a number counts as 0.
+    fn int(&self, name: &str) -> i64 {
+        match self.flags.get(name) {
+            Some(Value::Int(n)) => *n,
+            _ => 0,
         }
     }
-}
 
-// function pointer typedef so that we can map strings to room functions
-type Room = fn(&mut Inventory, &mut Flags) -> String;
+    fn set_int(&mut self, name: &str, n: i64) {
+        self.set_value(name, Value::Int(n));
+    }
+
+    // The variable as a string. Anything that isn't a string counts as empty.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn str(&self, name: &str) -> &str {
+        match self.flags.get(name) {
+            Some(Value::Str(s)) => s,
+            _ => "",
+        }
+    }
+
+    fn set_str(&mut self, name: &str, s: &str) {
+        self.set_value(name, Value::Str(String::from(s)));
+    }
+
+    // Add `by` to a counter, which starts from 0 if it isn't one yet.
+    fn inc(&mut self, name: &str, by: i64) {
+        self.set_int(name, self.int(name).saturating_add(by));
+    }
+
+    fn dec(&mut self, name: &str, by: i64) {
+        self.inc(name, by.saturating_neg());
+    }
+}
 
 // Inventory implementation
 impl Inventory {
@@ -102,199 +285,147 @@ impl Inventory {
     }
 
     // Adds the item to the vec
-    fn add(&mut self, item: &str, desc: &str) {
-        self.items.push((String::from(item), String::from(desc)));
+    fn add(&mut self, item: &str) {
+        self.items.push(String::from(item));
     }
 
-    // removes the item from the vec, if it exists
-    fn remove(&mut self, item: &str) {
-        match self.find(item) {
-            Some(i) => self.items.retain(|(itm, _)| itm != item),
-            None => {}
-        };
+    // removes the item from the vec, returning whether it was there
+    fn remove(&mut self, item: &str) -> bool {
+        self.find(item).map(|i| self.items.remove(i)).is_some()
     }
 
     // Returns an Option containing the index of the item if it was found
     fn find(&self, target_item: &str) -> Option<usize> {
-        for (i, tupl) in self.items.iter().enumerate() {
-            if target_item == tupl.0 {
-                return Some(i);
-            }
-        }
-        None
+        self.items.iter().position(|item| item == target_item)
     }
 
-    // Returns a bool indicating if the vec contains an item with the given name
+    // Returns a bool indicating if the vec contains an item with the given id
     fn has(&self, target_item: &str) -> bool {
         self.find(target_item).is_some()
     }
 }
 
-impl fmt::Display for Inventory {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "--- INVENTORY ---")?;
-        for (item, desc) in &self.items {
-            writeln!(f, "{: <10} | {: <10}", item, desc)?;
-        }
-        writeln!(f, "------------------")
+// Prompt for a line and parse the commands in it, in the order they were typed.
+// Returns Err once the console has run out of input.
+fn get_user_input(console: &mut dyn Console) -> Result<Vec<ParsedInput>, ()> {
+    console.print("> ");
+    match console.read_line() {
+        Some(line) => Ok(split_line(&line).into_iter().map(parse_input).collect()),
+        None => Err(()),
     }
 }
 
-// Essentially a wrapper for stdin().read_line().
-// Panics if stdout().flush() fails, for some weird reason.
-fn get_user_input() -> Result<ParsedInput, ()> {
-    let mut line = String::new();
-    print!("> ");
-    std::io::stdout().flush().expect("");
-    let _ = std::io::stdin().read_line(&mut line);
-
-    Ok::<ParsedInput, ()>(parse_input(line))
-}
+// Where the world definition is read from when no path is given on the command line.
+const DEFAULT_WORLD: &str = "worlds/test.world";
 
-// Parse messy, vague human language into easy-to-deal-with data.
-fn parse_input(s: String) -> ParsedInput {
-    // ...this sucks to have to do.
-    // This would probably just be easier with regex, but this is what I've resigned myself to, I guess.
-    match s.split_whitespace().collect::<Vec<_>>().as_slice() {
-        // Directions
-        ["n" | "N" | "north" | "North"]   => ParsedInput::North,
-        ["s" | "S" | "south" | "South"]   => ParsedInput::South,
-        ["e" | "E" | "east"  | "East"]    => ParsedInput::East,
-        ["w" | "W" | "west"  | "West"]    => ParsedInput::West,
-        ["u" | "U" | "up"    | "Up"]      => ParsedInput::Up,
-        ["d" | "D" | "down"  | "Down"]    => ParsedInput::Down,
-        // Meta-commands
-        ["i" | "I" | "inv"]               => ParsedInput::Inv,
-        ["q" | "Q" | "quit"]  | ["Quit"]  => ParsedInput::Quit,
-        // Actions
-        ["get", item @ ..] | ["take", item @ ..] | ["grab", item @ ..] => ParsedInput::Get(String::from(item.join(" "))),
-        ["look", "at", thing @ ..] | ["look", thing @ ..]                       => ParsedInput::Look(String::from(thing.join(" "))),
-        ["talk", "to", person @ ..]  | ["talk", person @ ..]                    => ParsedInput::Talk(String::from(person.join(" "))),
-        // Catch-all
-        all => {
-            let use_regex = Regex::new(r"^[uU]se\s+(.*)$").unwrap();
-            let use_on_regex = Regex::new(r"^[uU]se\s+(.*) on (.*)$").unwrap();
-
-            if let Some(captures) = use_on_regex.captures(&all.join(" "))
-            {
-                ParsedInput::UseOn(String::from(captures.get(1).map_or("", |m| m.as_str())), 
-                                   String::from(captures.get(2).map_or("", |m| m.as_str())))
-            }
-            else if let Some(captures) = use_regex.captures(&all.join(" "))
-            {
-                ParsedInput::Use(String::from(captures.get(1).map_or("", |m| m.as_str())))
-            }
-            else
-            {
-                ParsedInput::Other(String::from(all.join(" ")))
-            }
+// Usage: structs [--script <commands file>] [--undo <levels>] [world file]
+// With --script, commands are read one per line from the file instead of the terminal,
+// and the full transcript is printed once the script runs out.
+// --undo sets how many commands back `undo` can go.
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut script = None;
+    let mut undo_depth = None;
+    let mut path = String::from(DEFAULT_WORLD);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => match args.next() {
+                Some(file) => script = Some(file),
+                None => {
+                    eprintln!("--script needs a file of commands");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--undo" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => undo_depth = Some(n),
+                None => {
+                    eprintln!("--undo needs a number of levels");
+                    return ExitCode::FAILURE;
+                }
+            },
+            _ => path = arg,
         }
     }
-}
 
-fn test_room(inv: &mut Inventory, flags: &mut Flags) -> String {
-    // Exposition
-    println!("You find yourself standing inside of a developer's test room.");
-    flags.print_if("test_room_got_golden_key", false, "The room is bare, except for a small golden key gleaming gently in the middle of the room.");
-    println!("To the north is Room A.");
-
-    // Process user input
-    String::from(match get_user_input() {
-        Ok(ParsedInput::North) => "room_a",
-        Ok(ParsedInput::Look(_)) => "test_room",
-        Ok(ParsedInput::Inv) => { println!("{}", inv); "test_room" },
-        Ok(ParsedInput::Quit) => { exit(0) },
-        Ok(ParsedInput::Get(item)) => {
-            if item.contains("key") {
-                flags.set("test_room_got_golden_key");
-                inv.add("Golden Key", "A quaint key with an irresistable luster.");
-                println!("You pick up the gold key.");
-            }
-            "test_room"
-        }
-        _ => "test_room"
-    })
-}
-
-fn room_a(inv: &mut Inventory, flags: &mut Flags) -> String {
-    // Exposition
-    println!("You find yourself standing inside of Room A. Very clearly distinct from the last room. This one has a name!");
-    flags.print_if("room_a_opened_chest", false, "A chest sits alone in a dark corner of the room.");
-    println!("To the south is the test room.");
-
-    // Helper functions
-    fn open_chest(inv: &mut Inventory, flags: &mut Flags) {
-        if !flags.is_set("room_a_opened_chest") 
-        {
-            if inv.has("Golden Key") 
-            {
-                inv.remove("Golden Key");
-                flags.set("room_a_opened_chest");
-                println!("You opened the chest, and found a sword inside!");
-                inv.add("Sword", "You could do some damage with this.");
-            } 
-            else 
-            {
-                println!("The chest is locked. Maybe there's a key somewhere?");
-            }
-        } 
-        else 
-        {
-            println!("The chest is already open. Don't you remember the cool sword you got?");
+    let world = match World::load(&path) {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("Failed to load world: {}", e);
+            return ExitCode::FAILURE;
         }
+    };
+    let mut game = Game::new(world);
+    if let Some(depth) = undo_depth {
+        game.set_undo_depth(depth);
     }
 
-    // Process user input
-    String::from(match get_user_input() {
-        Ok(ParsedInput::South) => "test_room",
-        Ok(ParsedInput::Look(_)) => "room_a",
-        Ok(ParsedInput::Inv) => { println!("{}", inv); "room_a" },
-        Ok(ParsedInput::Quit) => { exit(0) },
-        // All of the ways to open the chest
-        Ok(ParsedInput::Use(item)) => {
-            if item.contains("key") {
-                open_chest(inv, flags); 
-            }
-            "room_a"
-        }
-        Ok(ParsedInput::UseOn(item, object)) => {
-            if item.contains("key") && object.contains("chest") {
-                open_chest(inv, flags);
-            }
-            "room_a"
+    let outcome = match script {
+        Some(file) => {
+            let commands = match fs::read_to_string(&file) {
+                Ok(source) => source.lines().map(String::from).collect::<Vec<_>>(),
+                Err(e) => {
+                    eprintln!("Failed to read script {}: {}", file, e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let mut console = Scripted::new(commands);
+            let outcome = game.run(&mut console);
+            print!("{}", console.transcript());
+            outcome
         }
-        Ok(ParsedInput::Other(text)) => {
-            if text.contains("open") && text.contains("chest") {
-                open_chest(inv, flags);
-            }
-            "room_a"
-        }
-        _ => "room_a"
-    })
-}
+        None => game.run(&mut Terminal::new()),
+    };
 
-fn dead_room(_inv: &mut Inventory, _flags: &mut Flags) -> String
-{
-    println!("Attempting to access a room that doesn't exist.");
-    exit(1);
+    match outcome {
+        Outcome::Quit => ExitCode::SUCCESS,
+        Outcome::Error(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() -> std::io::Result<()> {
-    let mut inv: Inventory = Inventory::new();
-    let mut flags: Flags = Flags::new();
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use super::{Flags, Value};
 
-    let mut room = String::from("test_room");
+    #[test]
+    fn values_and_comparisons() {
+        assert_eq!(Value::parse("true"), Value::Bool(true));
+        assert_eq!(Value::parse("-3"), Value::Int(-3));
+        assert_eq!(Value::parse("\"5\""), Value::Str(String::from("5")));
+        assert_eq!(Value::parse("hello"), Value::Str(String::from("hello")));
+        assert_eq!(Value::Int(2).compare(&Value::Int(10)), Some(Ordering::Less));
+        assert_eq!(Value::Int(2).compare(&Value::Str(String::from("2"))), None);
+    }
 
-    let mut rooms: HashMap<String, Room> = HashMap::new();
+    #[test]
+    fn counters() {
+        let mut flags = Flags::new();
+        assert_eq!(flags.int("visits"), 0);
+        flags.inc("visits", 3);
+        flags.dec("visits", 1);
+        assert_eq!(flags.int("visits"), 2);
+        flags.set_int("visits", i64::MAX);
+        flags.inc("visits", 1);
+        assert_eq!(flags.int("visits"), i64::MAX);
+        // A counter is set while it's non-zero, and flags keep working as before.
+        assert!(flags.is_set("visits"));
+        flags.set_int("visits", 0);
+        assert!(!flags.is_set("visits"));
+        flags.set("met");
+        assert!(flags.is_set("met"));
+    }
 
-    rooms.insert(String::from("test_room"), test_room);
-    rooms.insert(String::from("room_a"), room_a);
-    
-    loop {
-        room = match rooms.get(&room) {
-            Some(room_fn) => *room_fn,
-            _ => dead_room
-        }(&mut inv, &mut flags);
+    #[test]
+    fn strings() {
+        let mut flags = Flags::new();
+        assert_eq!(flags.str("mood"), "");
+        flags.set_str("mood", "angry");
+        assert_eq!(flags.str("mood"), "angry");
+        // A variable of another type isn't a string at all.
+        flags.set_int("visits", 3);
+        assert_eq!(flags.str("visits"), "");
     }
 }
-
@@ -0,0 +1,399 @@
+use crate::ParsedInput;
+
+// Command grammar.
+// A line is lowercased, split into words and articles are dropped. The longest verb phrase at
+// the start is looked up in VERBS to find its canonical verb (a word that's the start of exactly
+// one verb counts too, so "exa" is "examine"), and the rest is split at the first preposition
+// into a direct and an indirect object:
+//
+//   pick up the key              -> get  "key"
+//   take key from the chest      -> get  "key"    from "chest"
+//   put the sword into the chest -> put  "sword"  in   "chest"
+//
+// ParsedInput is then derived from that structure.
+
+pub struct Command {
+    pub verb: String,
+    pub object: String,
+    pub prep: Option<&'static str>,
+    pub target: String,
+}
+
+const ARTICLES: [&str; 3] = ["the", "a", "an"];
+
+// Prepositions and what they mean, so "into" is understood the same as "in".
+const PREPOSITIONS: [(&str, &str); 9] = [
+    ("in", "in"), ("into", "in"), ("inside", "in"),
+    ("on", "on"), ("onto", "on"),
+    ("with", "with"),
+    ("from", "from"), ("out of", "from"),
+    ("to", "to"),
+];
+
+// Ways of naming a direction, used both on their own ("ne") and after "go" ("go northeast").
+const DIRECTIONS: &[(&str, &str)] = &[
+    ("n", "north"), ("north", "north"),
+    ("s", "south"), ("south", "south"),
+    ("e", "east"),  ("east", "east"),
+    ("w", "west"),  ("west", "west"),
+    ("ne", "northeast"), ("northeast", "northeast"),
+    ("nw", "northwest"), ("northwest", "northwest"),
+    ("se", "southeast"), ("southeast", "southeast"),
+    ("sw", "southwest"), ("southwest", "southwest"),
+    ("u", "up"),    ("up", "up"),
+    ("d", "down"),  ("down", "down"),
+    ("in", "in"),   ("inside", "in"), ("enter", "in"),
+    ("out", "out"), ("outside", "out"), ("exit", "out"), ("leave", "out"),
+];
+
+// Verb phrases and the canonical verb they stand for.
+const VERBS: &[(&str, &str)] = &[
+    // Movement
+    ("go", "go"), ("walk", "go"), ("run", "go"), ("head", "go"),
+    // Meta-commands
+    ("i", "inventory"), ("inv", "inventory"), ("inventory", "inventory"),
+    ("q", "quit"), ("quit", "quit"),
+    ("h", "help"), ("help", "help"), ("?", "help"),
+    ("save", "save"),
+    ("load", "load"), ("restore", "load"),
+    ("g", "again"), ("again", "again"),
+    ("undo", "undo"),
+    ("flags", "flags"),
+    ("verbose", "verbose"), ("brief", "brief"), ("superbrief", "superbrief"),
+    // Actions
+    ("get", "get"), ("take", "get"), ("grab", "get"), ("pick up", "get"),
+    ("drop", "drop"), ("put down", "drop"), ("discard", "drop"),
+    ("put", "put"), ("place", "put"), ("insert", "put"),
+    ("use", "use"),
+    ("open", "open"), ("close", "close"), ("shut", "close"),
+    ("lock", "lock"), ("unlock", "unlock"),
+    ("look", "look"), ("look at", "look"), ("l", "look"), ("examine", "look"), ("x", "look"),
+    ("talk", "talk"), ("talk to", "talk"), ("talk with", "talk"), ("speak to", "talk"), ("speak with", "talk"),
+];
+
+// Every single-word verb and direction the grammar understands.
+pub fn known_verbs() -> impl Iterator<Item = &'static str> {
+    DIRECTIONS.iter().chain(VERBS).map(|(phrase, _)| *phrase).filter(|p| !p.contains(' '))
+}
+
+// Find the longest phrase from `table` that `words` starts with.
+// Returns what the phrase stands for and how many words it covers.
+fn longest_phrase(words: &[&str], table: &[(&str, &'static str)]) -> Option<(&'static str, usize)> {
+    table.iter()
+        .filter_map(|(phrase, meaning)| {
+            let len = phrase.split(' ').count();
+            (words.len() >= len && words[..len].join(" ") == *phrase).then_some((*meaning, len))
+        })
+        .max_by_key(|(_, len)| *len)
+}
+
+// If `word` is the start of one-word phrases that all mean the same thing, that meaning.
+fn expand_prefix(word: &str, table: &[(&str, &'static str)]) -> Option<&'static str> {
+    let mut meanings = table.iter()
+        .filter(|(phrase, _)| !phrase.contains(' ') && phrase.starts_with(word))
+        .map(|(_, meaning)| *meaning);
+    let first = meanings.next()?;
+    meanings.all(|m| m == first).then_some(first)
+}
+
+// The verb at the start of `words`, and how many words it takes up.
+fn find_verb(words: &[&str]) -> Option<(&'static str, usize)> {
+    let table: Vec<(&str, &'static str)> = DIRECTIONS.iter().chain(VERBS).copied().collect();
+    longest_phrase(words, &table).or_else(|| Some((expand_prefix(words.first()?, &table)?, 1)))
+}
+
+// Break a line down into verb, object, preposition and target.
+// Unknown verbs are kept as typed; only the articles are removed.
+pub fn parse_command(s: &str) -> Command {
+    let s = s.to_lowercase();
+    let words: Vec<&str> = s.split_whitespace().filter(|w| !ARTICLES.contains(w)).collect();
+
+    let (verb, rest) = match find_verb(&words) {
+        // "go north" means the same as "north".
+        Some(("go", 1)) if words.len() > 1 => match longest_phrase(&words[1..], DIRECTIONS) {
+            Some((dir, len)) if len == words.len() - 1 => (String::from(dir), &words[words.len()..]),
+            _ => (String::from("go"), &words[1..]),
+        },
+        Some((verb, len)) => (String::from(verb), &words[len..]),
+        None => match words.split_first() {
+            Some((verb, rest)) => (String::from(*verb), rest),
+            None => (String::new(), &words[..]),
+        },
+    };
+
+    for i in 0..rest.len() {
+        if let Some((prep, len)) = longest_phrase(&rest[i..], &PREPOSITIONS) {
+            return Command {
+                verb,
+                object: rest[..i].join(" "),
+                prep: Some(prep),
+                target: rest[i + len..].join(" "),
+            };
+        }
+    }
+    Command { verb, object: rest.join(" "), prep: None, target: String::new() }
+}
+
+// Words that end in a full stop without ending a sentence, as in "talk to Mr. Smith".
+const ABBREVIATIONS: [&str; 5] = ["mr", "mrs", "ms", "dr", "st"];
+
+// Split a line at every `;`, and at every `.` that ends a sentence: one followed by whitespace
+// or the end of the line, and not after an abbreviation. So "load ../old" stays in one piece.
+fn sentences(line: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        let ends = match c {
+            ';' => true,
+            '.' => {
+                let word = line[start..i].rsplit(char::is_whitespace).next().unwrap_or("").to_lowercase();
+                line[i + 1..].chars().next().is_none_or(char::is_whitespace) && !ABBREVIATIONS.contains(&word.as_str())
+            }
+            _ => false,
+        };
+        if ends {
+            sentences.push(&line[start..i]);
+            start = i + 1;
+        }
+    }
+    sentences.push(&line[start..]);
+    sentences
+}
+
+// Split a line holding several commands, like "get key. n. use key on chest" or
+// "get key, then go north", into the commands in the order they should be run.
+pub fn split_line(line: &str) -> Vec<String> {
+    let mut commands = vec![];
+    for sentence in sentences(line) {
+        let mut words: Vec<&str> = vec![];
+        for word in sentence.split_whitespace().chain(["then"]) {
+            if !word.eq_ignore_ascii_case("then") {
+                words.push(word);
+                continue;
+            }
+            // "get key and then n", "get key, then n"
+            if words.last().is_some_and(|w| w.eq_ignore_ascii_case("and")) {
+                words.pop();
+            }
+            let command = words.join(" ");
+            let command = command.trim_matches(|c: char| c == ',' || c.is_whitespace());
+            if !command.is_empty() {
+                commands.push(String::from(command));
+            }
+            words.clear();
+        }
+    }
+    commands
+}
+
+// Everything after the first word of the line, exactly as typed.
+fn after_verb(s: &str) -> &str {
+    s.trim().split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim())
+}
+
+// Parse messy, vague human language into easy-to-deal-with data.
+pub fn parse_input(s: String) -> ParsedInput {
+    let Command { verb, object, prep, target } = parse_command(&s);
+    let bare = object.is_empty() && prep.is_none();
+
+    match (verb.as_str(), prep) {
+        // Directions
+        ("north", _)     if bare => ParsedInput::North,
+        ("south", _)     if bare => ParsedInput::South,
+        ("east", _)      if bare => ParsedInput::East,
+        ("west", _)      if bare => ParsedInput::West,
+        ("northeast", _) if bare => ParsedInput::NorthEast,
+        ("northwest", _) if bare => ParsedInput::NorthWest,
+        ("southeast", _) if bare => ParsedInput::SouthEast,
+        ("southwest", _) if bare => ParsedInput::SouthWest,
+        ("up", _)        if bare => ParsedInput::Up,
+        ("down", _)      if bare => ParsedInput::Down,
+        ("in", _)        if bare => ParsedInput::In,
+        ("out", _)       if bare => ParsedInput::Out,
+        // Meta-commands
+        ("inventory", _) if bare => ParsedInput::Inv,
+        ("quit", _)      if bare => ParsedInput::Quit,
+        ("help", _)      if bare => ParsedInput::Help,
+        ("again", _)     if bare => ParsedInput::Again,
+        ("undo", _)      if bare => ParsedInput::Undo,
+        ("flags", _)     if bare => ParsedInput::Flags,
+        ("verbose", _)   if bare => ParsedInput::Verbose,
+        ("brief", _)     if bare => ParsedInput::Brief,
+        ("superbrief", _) if bare => ParsedInput::Superbrief,
+        // Slots are taken as typed, so `save a` doesn't lose its slot to the article-dropping.
+        ("save", _) => ParsedInput::Save(String::from(after_verb(&s))),
+        ("load", _) => ParsedInput::Load(String::from(after_verb(&s))),
+        // Actions
+        ("get", Some("from"))     => ParsedInput::GetFrom(object, target),
+        ("get", None)             => ParsedInput::Get(object),
+        ("drop", None)            => ParsedInput::Drop(object),
+        ("put", Some("in" | "on")) => ParsedInput::PutIn(object, target),
+        ("use", Some("on" | "with")) => ParsedInput::UseOn(object, target),
+        ("use", None)             => ParsedInput::Use(object),
+        ("open", None)            => ParsedInput::Open(object),
+        ("close", None)           => ParsedInput::Close(object),
+        ("lock", Some("with"))    => ParsedInput::Lock(object, target),
+        ("lock", None)            => ParsedInput::Lock(object, String::new()),
+        ("unlock", Some("with"))  => ParsedInput::Unlock(object, target),
+        ("unlock", None)          => ParsedInput::Unlock(object, String::new()),
+        // "look at the chest", "look in the chest", "talk to tom", "talk with tom"
+        ("look", Some(_)) if object.is_empty() => ParsedInput::Look(target),
+        ("look", None)            => ParsedInput::Look(object),
+        ("talk", Some(_)) if object.is_empty() => ParsedInput::Talk(target),
+        ("talk", None)            => ParsedInput::Talk(object),
+        // Catch-all
+        _ => {
+            let mut words = vec![verb, object];
+            if let Some(prep) = prep {
+                words.push(String::from(prep));
+                words.push(target);
+            }
+            words.retain(|w| !w.is_empty());
+            ParsedInput::Other(words.join(" "))
+        }
+    }
+}
+
+impl ParsedInput {
+    // The input as (verb, object, preposition, target), for matching against world file patterns.
+    // Prepositions are canonical and empty when there isn't one; movement and meta-commands have no parts.
+    pub fn parts(&self) -> Option<(&str, &str, &str, &str)> {
+        Some(match self {
+            ParsedInput::Get(s)           => ("get", s.as_str(), "", ""),
+            ParsedInput::GetFrom(s, t)    => ("get", s.as_str(), "from", t.as_str()),
+            ParsedInput::Drop(s)          => ("drop", s.as_str(), "", ""),
+            ParsedInput::PutIn(s, t)      => ("put", s.as_str(), "in", t.as_str()),
+            ParsedInput::Use(s)           => ("use", s.as_str(), "", ""),
+            ParsedInput::UseOn(s, t)      => ("use", s.as_str(), "on", t.as_str()),
+            ParsedInput::Look(s)          => ("look", s.as_str(), "", ""),
+            ParsedInput::Talk(s)          => ("talk", s.as_str(), "", ""),
+            ParsedInput::Open(s)          => ("open", s.as_str(), "", ""),
+            ParsedInput::Close(s)         => ("close", s.as_str(), "", ""),
+            ParsedInput::Lock(s, t)       => ("lock", s.as_str(), if t.is_empty() { "" } else { "with" }, t.as_str()),
+            ParsedInput::Unlock(s, t)     => ("unlock", s.as_str(), if t.is_empty() { "" } else { "with" }, t.as_str()),
+            // Anything the grammar didn't understand keeps its prepositions as ordinary words.
+            ParsedInput::Other(s)         => {
+                let (verb, rest) = s.split_once(' ').unwrap_or((s.as_str(), ""));
+                (verb, rest, "", "")
+            }
+            _ => return None,
+        })
+    }
+}
+
+// What "it" and "them" stand for: whatever the player most recently referred to by name.
+#[derive(Default)]
+pub struct Referents {
+    last: Vec<String>,
+}
+
+impl Referents {
+    // Remember the things a command just referred to, forgetting whatever came before.
+    pub fn remember(&mut self, names: &[String]) {
+        if !names.is_empty() {
+            self.last = names.to_vec();
+        }
+    }
+
+    // Swap "it" and "them" in the input for the names of what they refer to.
+    pub fn substitute(&self, input: ParsedInput) -> Result<ParsedInput, String> {
+        let mut error = None;
+        let input = input.map_phrases(|phrase| {
+            let words = phrase.split_whitespace().map(|w| match (w, self.last.last()) {
+                ("it", Some(name)) => name.to_lowercase(),
+                ("them", Some(_)) => self.last.join(", ").to_lowercase(),
+                ("it" | "them", None) => {
+                    error = Some(format!("I'm not sure what \"{}\" refers to.", w));
+                    String::from(w)
+                }
+                _ => String::from(w),
+            });
+            words.collect::<Vec<_>>().join(" ")
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(input),
+        }
+    }
+}
+
+impl ParsedInput {
+    // Rewrite every noun phrase in the input. Meta-commands and directions are left alone.
+    fn map_phrases<F: FnMut(&str) -> String>(self, mut f: F) -> ParsedInput {
+        match self {
+            ParsedInput::Look(s)       => ParsedInput::Look(f(&s)),
+            ParsedInput::Get(s)        => ParsedInput::Get(f(&s)),
+            ParsedInput::GetFrom(s, t) => ParsedInput::GetFrom(f(&s), f(&t)),
+            ParsedInput::PutIn(s, t)   => ParsedInput::PutIn(f(&s), f(&t)),
+            ParsedInput::Use(s)        => ParsedInput::Use(f(&s)),
+            ParsedInput::UseOn(s, t)   => ParsedInput::UseOn(f(&s), f(&t)),
+            ParsedInput::Talk(s)       => ParsedInput::Talk(f(&s)),
+            ParsedInput::Drop(s)       => ParsedInput::Drop(f(&s)),
+            ParsedInput::Open(s)       => ParsedInput::Open(f(&s)),
+            ParsedInput::Close(s)      => ParsedInput::Close(f(&s)),
+            ParsedInput::Lock(s, t)    => ParsedInput::Lock(f(&s), f(&t)),
+            ParsedInput::Unlock(s, t)  => ParsedInput::Unlock(f(&s), f(&t)),
+            ParsedInput::Other(s)      => ParsedInput::Other(f(&s)),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ParsedInput;
+    use super::{parse_command, parse_input, split_line, Referents};
+
+    fn parse(s: &str) -> ParsedInput {
+        parse_input(String::from(s))
+    }
+
+    #[test]
+    fn verb_phrases_and_prepositions() {
+        let command = parse_command("Take the key out of the chest");
+        assert_eq!((command.verb.as_str(), command.object.as_str(), command.prep, command.target.as_str()),
+                   ("get", "key", Some("from"), "chest"));
+        assert_eq!(parse("pick up an oily rag"), ParsedInput::Get(String::from("oily rag")));
+        assert_eq!(parse("put sword into chest"), ParsedInput::PutIn(String::from("sword"), String::from("chest")));
+        assert_eq!(parse("use key on chest"), ParsedInput::UseOn(String::from("key"), String::from("chest")));
+        assert_eq!(parse("talk with tom"), ParsedInput::Talk(String::from("tom")));
+        assert_eq!(parse("save a"), ParsedInput::Save(String::from("a")));
+        assert_eq!(parse("restore The-End"), ParsedInput::Load(String::from("The-End")));
+    }
+
+    #[test]
+    fn directions_and_unknown_verbs() {
+        assert_eq!(parse("n"), ParsedInput::North);
+        assert_eq!(parse("look at the chest"), ParsedInput::Look(String::from("chest")));
+        assert_eq!(parse("xyzzy the lamp"), ParsedInput::Other(String::from("xyzzy lamp")));
+    }
+
+    #[test]
+    fn directions_and_prefixes() {
+        assert_eq!(parse("NE"), ParsedInput::NorthEast);
+        assert_eq!(parse("go north"), ParsedInput::North);
+        assert_eq!(parse("exa key"), ParsedInput::Look(String::from("key")));
+        // "go" somewhere that isn't a direction is left for the room to make sense of.
+        assert_eq!(parse("go home"), ParsedInput::Other(String::from("go home")));
+    }
+
+    #[test]
+    fn it_and_them() {
+        let mut referents = Referents::default();
+        assert!(referents.substitute(parse("get it")).is_err());
+        referents.remember(&[String::from("Golden Key")]);
+        assert_eq!(referents.substitute(parse("drop it")), Ok(ParsedInput::Drop(String::from("golden key"))));
+        referents.remember(&[String::from("Sword"), String::from("Oily Rag")]);
+        assert_eq!(referents.substitute(parse("put them in chest")),
+                   Ok(ParsedInput::PutIn(String::from("sword, oily rag"), String::from("chest"))));
+    }
+
+    #[test]
+    fn splitting_lines() {
+        assert_eq!(split_line("get key. n; use key on chest"), ["get key", "n", "use key on chest"]);
+        assert_eq!(split_line("get key, and then go north then look"), ["get key", "go north", "look"]);
+        assert_eq!(split_line("load ../old"), ["load ../old"]);
+        assert_eq!(split_line("talk to Mr. Smith."), ["talk to Mr. Smith"]);
+        assert!(split_line(" . ;").is_empty());
+    }
+}
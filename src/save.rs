@@ -0,0 +1,166 @@
+use std::{fs, path::PathBuf, collections::{HashMap, HashSet}};
+use crate::{game::State, Flags, Inventory, Value};
+
+// Saved games.
+// A save is a small line-based text file in `saves/<slot>.sav`:
+//
+//   encrusted-save <version>
+//   room <room id>
+//   flag <name> <true|false>
+//   int <name> <number>
+//   str <name> <text>
+//   item <item id>
+//   place <room or container id> <item id>
+//   visited <room id>
+//
+// The version is bumped whenever the layout changes, and files from any other
+// version are refused rather than half-loaded.
+
+const SAVE_DIR: &str = "saves";
+const MAGIC: &str = "encrusted-save";
+const SAVE_VERSION: u32 = 5;
+
+// Slots become file names, so keep them to something that can't escape the save directory.
+fn slot_path(slot: &str) -> Result<PathBuf, String> {
+    if slot.is_empty() {
+        return Err(String::from("Which slot? Try something like `save 1`."));
+    }
+    if !slot.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("`{}` isn't a valid slot name. Use letters, numbers, `-` and `_`.", slot));
+    }
+    Ok(PathBuf::from(SAVE_DIR).join(format!("{}.sav", slot)))
+}
+
+pub fn save(slot: &str, state: &State) -> Result<(), String> {
+    let path = slot_path(slot)?;
+    let State { room, inv, flags, contents, visited } = state;
+
+    let mut out = format!("{} {}\nroom {}\n", MAGIC, SAVE_VERSION, room);
+    // Sort the flags so the same state always produces the same file.
+    let mut names: Vec<_> = flags.flags.keys().collect();
+    names.sort();
+    for name in names {
+        out += &match &flags.flags[name] {
+            Value::Bool(b) => format!("flag {} {}\n", name, b),
+            Value::Int(n) => format!("int {} {}\n", name, n),
+            Value::Str(s) => format!("str {} {}\n", name, s),
+        };
+    }
+    for item in &inv.items {
+        out += &format!("item {}\n", item);
+    }
+    let mut places: Vec<_> = contents.keys().collect();
+    places.sort();
+    for place in places {
+        for item in &contents[place].items {
+            out += &format!("place {} {}\n", place, item);
+        }
+    }
+    let mut visited: Vec<_> = visited.iter().collect();
+    visited.sort();
+    for room in visited {
+        out += &format!("visited {}\n", room);
+    }
+
+    fs::create_dir_all(SAVE_DIR).map_err(|e| format!("Couldn't create `{}`: {}", SAVE_DIR, e))?;
+    fs::write(&path, out).map_err(|e| format!("Couldn't write `{}`: {}", path.display(), e))
+}
+
+pub fn load(slot: &str) -> Result<State, String> {
+    let path = slot_path(slot)?;
+    let source = fs::read_to_string(&path).map_err(|_| format!("There's no save in slot `{}`.", slot))?;
+    let corrupt = |n: usize| format!("`{}` is corrupt (line {}).", path.display(), n);
+
+    let mut lines = source.lines().enumerate().map(|(i, l)| (i + 1, l));
+
+    // Check the header before trying to make sense of anything else.
+    let version = match lines.next().and_then(|(_, l)| l.strip_prefix(MAGIC)) {
+        Some(v) => v.trim().parse::<u32>().map_err(|_| corrupt(1))?,
+        None => return Err(format!("`{}` isn't a save file.", path.display())),
+    };
+    if version != SAVE_VERSION {
+        return Err(format!("Slot `{}` was saved with save format version {}, but this build only understands version {}.",
+                           slot, version, SAVE_VERSION));
+    }
+
+    let mut room = None;
+    let mut inv = Inventory::new();
+    let mut flags = Flags::new();
+    let mut contents: HashMap<String, Inventory> = HashMap::new();
+    let mut visited = HashSet::new();
+    for (n, line) in lines {
+        match line.split_once(' ') {
+            Some(("room", id)) => room = Some(String::from(id)),
+            Some(("flag", rest)) => match rest.split_once(' ') {
+                Some((name, "true"))  => flags.set_as(name, true),
+                Some((name, "false")) => flags.set_as(name, false),
+                _ => return Err(corrupt(n)),
+            },
+            Some(("int", rest)) => match rest.split_once(' ').and_then(|(name, n)| Some((name, n.parse::<i64>().ok()?))) {
+                Some((name, val)) => flags.set_int(name, val),
+                None => return Err(corrupt(n)),
+            },
+            Some(("str", rest)) => match rest.split_once(' ') {
+                Some((name, val)) => flags.set_str(name, val),
+                None => return Err(corrupt(n)),
+            },
+            Some(("item", item)) => inv.add(item),
+            Some(("place", rest)) => match rest.split_once(' ') {
+                Some((place, item)) => contents.entry(String::from(place)).or_insert_with(Inventory::new).add(item),
+                None => return Err(corrupt(n)),
+            },
+            Some(("visited", room)) => { visited.insert(String::from(room)); }
+            _ if line.is_empty() => {}
+            _ => return Err(corrupt(n)),
+        }
+    }
+
+    match room {
+        Some(room) => Ok(State { room, inv, flags, contents, visited }),
+        None => Err(format!("`{}` doesn't record a room.", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, collections::{HashMap, HashSet}};
+    use crate::{game::State, Flags, Inventory};
+    use super::{load, save, slot_path};
+
+    #[test]
+    fn round_trip() {
+        let mut inv = Inventory::new();
+        inv.add("golden_key");
+        let mut flags = Flags::new();
+        flags.set("opened_chest");
+        flags.set_as("met", false);
+        flags.set_int("room_a.visits", 2);
+        let mut chest = Inventory::new();
+        chest.add("sword");
+        let state = State {
+            room: String::from("room_a"),
+            inv,
+            flags,
+            contents: HashMap::from([(String::from("chest"), chest)]),
+            visited: HashSet::from([String::from("room_b")]),
+        };
+
+        let slot = "test-round-trip";
+        save(slot, &state).unwrap();
+        let loaded = load(slot);
+        fs::remove_file(slot_path(slot).unwrap()).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.room, state.room);
+        assert_eq!(loaded.inv.items, state.inv.items);
+        assert_eq!(loaded.flags.flags, state.flags.flags);
+        assert_eq!(loaded.contents["chest"].items, state.contents["chest"].items);
+        assert_eq!(loaded.visited, state.visited);
+    }
+
+    #[test]
+    fn slot_names() {
+        assert!(slot_path("quick-1").is_ok());
+        assert!(slot_path("").is_err());
+        assert!(slot_path("../etc").is_err());
+    }
+}
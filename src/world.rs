@@ -0,0 +1,1132 @@
+use std::{cmp::Ordering, fmt, fs, collections::{HashMap, VecDeque}};
+use crate::{console::Console, game::{State, Transition}, parser::{parse_input, Referents}, Flags, Inventory, ParsedInput, Value};
+
+mod dialogue;
+mod item;
+mod resolve;
+mod suggest;
+mod template;
+use dialogue::Npc;
+use item::{Capacity, Item};
+use resolve::{list_choices, mentions, Resolution};
+use template::Template;
+
+// World definitions.
+// Rooms, exits, items and scripted reactions are described in a plain-text
+// world file and loaded once at startup, so new content doesn't need a rebuild.
+// See worlds/test.world for a commented example of the format.
+
+// A single precondition on the game state, e.g. `not opened_chest`, `has golden_key`
+// or `gold >= 5`.
+enum Cond {
+    Flag(String, bool),
+    Has(String, bool),
+    Compare(String, Op, Value),
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Longer operators first, so `>=` isn't read as `>`.
+const OPS: [(&str, Op); 6] = [
+    ("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt),
+];
+
+impl Cond {
+    fn holds(&self, inv: &Inventory, flags: &Flags) -> bool {
+        match self {
+            Cond::Flag(flag, val) => flags.is_set(flag) == *val,
+            Cond::Has(item, val)  => inv.has(item) == *val,
+            Cond::Compare(var, op, val) => {
+                // A variable that's never been set is 0, false or empty, whichever fits.
+                let ord = match flags.get(var) {
+                    Some(current) => current.compare(val),
+                    None => val.empty().compare(val),
+                };
+                match (op, ord) {
+                    (Op::Ne, ord) => ord != Some(Ordering::Equal),
+                    (_, None) => false,
+                    (Op::Eq, Some(ord)) => ord == Ordering::Equal,
+                    (Op::Lt, Some(ord)) => ord == Ordering::Less,
+                    (Op::Le, Some(ord)) => ord != Ordering::Greater,
+                    (Op::Gt, Some(ord)) => ord == Ordering::Greater,
+                    (Op::Ge, Some(ord)) => ord != Ordering::Less,
+                }
+            }
+        }
+    }
+}
+
+fn all_hold(conds: &[Cond], inv: &Inventory, flags: &Flags) -> bool {
+    conds.iter().all(|c| c.holds(inv, flags))
+}
+
+// Something that happens when a trigger fires or a conversation reaches a node.
+enum Effect {
+    Say(String),
+    Set(String, Value),
+    Inc(String, i64),
+    Dec(String, i64),
+    Give(String),
+    Take(String),
+    Go(String),
+}
+
+// A command pattern like `use key on chest`.
+// Patterns go through the same grammar as the player's commands, so `pick up key` and
+// `get key` are the same pattern. Every word listed in the pattern has to show up as a whole
+// word in the matching part of the player's command.
+struct Pattern {
+    verb: String,
+    object: Vec<String>,
+    prep: String,
+    target: Vec<String>,
+}
+
+impl Pattern {
+    fn parse(s: &str) -> Option<Pattern> {
+        let input = parse_input(String::from(s));
+        let (verb, object, prep, target) = input.parts()?;
+        let words = |s: &str| s.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>();
+        if verb.is_empty() {
+            return None;
+        }
+        Some(Pattern { verb: verb.to_lowercase(), object: words(object), prep: String::from(prep), target: words(target) })
+    }
+
+    fn matches(&self, input: &ParsedInput) -> bool {
+        let (verb, object, prep, target) = match input.parts() {
+            Some(parts) => parts,
+            None => return false,
+        };
+        // Whole words only, so `key` doesn't match "monkey".
+        let contains_all = |text: &str, words: &[String]| {
+            let text: Vec<String> = text.split_whitespace().map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()).collect();
+            words.iter().all(|w| text.contains(w))
+        };
+
+        verb.eq_ignore_ascii_case(&self.verb)
+            && contains_all(object, &self.object)
+            && prep == self.prep
+            && contains_all(target, &self.target)
+    }
+}
+
+// One branch of a trigger. The first case whose conditions hold is the one that runs.
+struct Case {
+    conds: Vec<Cond>,
+    effects: Vec<Effect>,
+}
+
+struct Trigger {
+    patterns: Vec<Pattern>,
+    cases: Vec<Case>,
+}
+
+// A way out of a room. While `conds` don't hold the exit is either blocked with a
+// message, or, if there is no message, treated as if it wasn't there at all. An exit
+// through a door can only be taken while the door is open.
+struct Exit {
+    dir: String,
+    dest: String,
+    door: Option<String>,
+    conds: Vec<Cond>,
+    blocked: Option<String>,
+}
+
+// Something in a room that can be looked at but not picked up, like a chest.
+struct Thing {
+    name: String,
+    conds: Vec<Cond>,
+    desc: String,
+}
+
+pub struct Room {
+    // What the room is called in brief descriptions.
+    name: String,
+    text: Vec<(Vec<Cond>, Template)>,
+    exits: Vec<Exit>,
+    things: Vec<Thing>,
+    // Items lying in the room when the game starts.
+    items: Vec<String>,
+    // Indices into `World::npcs` of everyone standing in the room.
+    npcs: Vec<usize>,
+    triggers: Vec<Trigger>,
+}
+
+// How much of a room `describe` shows.
+pub enum Detail {
+    // The room's full text, what's lying in it, its exits and who's there.
+    Full,
+    // The same, with just the room's name in place of its text.
+    Short,
+    Name,
+}
+
+pub struct World {
+    pub start: String,
+    pub rooms: HashMap<String, Room>,
+    // Keyed by id.
+    items: HashMap<String, Item>,
+    npcs: Vec<Npc>,
+    // Every declared state variable, by full name, with the value it starts the game with.
+    vars: HashMap<String, Value>,
+    capacity: Capacity,
+}
+
+// Error produced while loading a world file. `line` is 1-based; 0 means the error isn't tied to a line.
+#[derive(Debug)]
+pub struct LoadError {
+    path: String,
+    line: usize,
+    msg: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.path, self.msg)
+        } else {
+            write!(f, "{}:{}: {}", self.path, self.line, self.msg)
+        }
+    }
+}
+
+const DIRECTIONS: [&str; 12] = [
+    "north", "south", "east", "west",
+    "northeast", "northwest", "southeast", "southwest",
+    "up", "down", "in", "out",
+];
+
+// The state variables declared so far, and which room or NPC block the parser is in.
+// Variables have to be declared before they're used. Those declared inside a block belong to
+// it: `flag opened_chest` inside `room room_a` is `room_a.opened_chest`, which the room itself
+// can call just `opened_chest`, and anything else has to call by its full name.
+struct Scope<'a> {
+    vars: &'a HashMap<String, Value>,
+    ns: Option<&'a str>,
+}
+
+impl Scope<'_> {
+    // The full name of the variable `name` refers to here.
+    fn resolve(&self, name: &str) -> Result<String, String> {
+        let local = self.ns.map(|ns| format!("{}.{}", ns, name));
+        match local {
+            Some(local) if !name.contains('.') && self.vars.contains_key(&local) => Ok(local),
+            _ if self.vars.contains_key(name) => Ok(String::from(name)),
+            _ => Err(format!("unknown flag `{}`; flags have to be declared with `flag` or `var` before they're used", name)),
+        }
+    }
+
+    // Like `resolve`, but the variable also has to have been declared to hold the same kind of
+    // value as `val`, so a counter can't be set to a string or a flag counted up.
+    fn resolve_typed(&self, name: &str, val: &Value) -> Result<String, String> {
+        let full = self.resolve(name)?;
+        let declared = &self.vars[&full];
+        if declared.kind() != val.kind() {
+            return Err(format!("`{}` holds a {}, not a {} like `{}`", name, declared.kind(), val.kind(), val));
+        }
+        Ok(full)
+    }
+}
+
+// Parse a comma-separated list of conditions. Items they mention are checked once the whole
+// file has been read, against line `n`.
+fn parse_conds(s: &str, n: usize, refs: &mut Refs, scope: &Scope) -> Result<Vec<Cond>, String> {
+    let mut conds = vec![];
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if let Some((var, op, val)) = OPS.iter().find_map(|&(sym, op)| part.split_once(sym).map(|(var, val)| (var.trim(), op, val.trim()))) {
+            if var.is_empty() || var.contains(char::is_whitespace) || val.is_empty() {
+                return Err(format!("malformed comparison `{}`", part));
+            }
+            conds.push(Cond::Compare(scope.resolve(var)?, op, Value::parse(val)));
+            continue;
+        }
+        let (val, rest) = match part.strip_prefix("not ") {
+            Some(rest) => (false, rest.trim()),
+            None => (true, part),
+        };
+        match rest.strip_prefix("has ").or_else(|| rest.strip_prefix("has:")) {
+            Some(item) => {
+                refs.items.push((n, String::from(item.trim())));
+                conds.push(Cond::Has(String::from(item.trim()), val));
+            }
+            None if rest.contains(char::is_whitespace) => return Err(format!("malformed condition `{}`", part)),
+            None => conds.push(Cond::Flag(scope.resolve(rest)?, val)),
+        }
+    }
+    Ok(conds)
+}
+
+// A line number and message describing why a world file couldn't be parsed.
+type ParseError = (usize, String);
+
+// Which block of the world file the parser is currently inside.
+enum Section {
+    Top,
+    Item(String),
+    Room(String),
+    Npc(usize),
+}
+
+// Names that can only be checked once the whole file has been read.
+#[derive(Default)]
+struct Refs {
+    rooms: Vec<(usize, String)>,
+    items: Vec<(usize, String)>,
+    doors: Vec<(usize, String)>,
+    // Every `contains`, in the order they appear.
+    placed: Vec<(usize, String)>,
+}
+
+// Parse `say`, `set`, `clear`, `inc`, `dec`, `give`, `take` or `go`. Returns None for any other keyword.
+fn parse_effect(keyword: &str, rest: &str, n: usize, refs: &mut Refs, scope: &Scope) -> Result<Option<Effect>, ParseError> {
+    if !matches!(keyword, "say" | "set" | "clear" | "inc" | "dec" | "give" | "take" | "go") {
+        return Ok(None);
+    }
+    if rest.is_empty() {
+        return Err((n, format!("`{}` needs an argument", keyword)));
+    }
+    let arg = String::from(rest);
+    // Effects can only store the kind of value the variable was declared with.
+    let set = |name: &str, val: Value| match scope.resolve_typed(name.trim(), &val) {
+        Ok(name) => Ok(Effect::Set(name, val)),
+        Err(e) => Err((n, e)),
+    };
+    Ok(Some(match keyword {
+        "say"   => Effect::Say(arg),
+        // `set <flag>` or `set <variable> = <value>`
+        "set"   => match rest.split_once('=') {
+            Some((name, val)) => set(name, Value::parse(val.trim()))?,
+            None => set(rest, Value::Bool(true))?,
+        },
+        "clear" => set(rest, Value::Bool(false))?,
+        // `inc <counter> [<amount>]`, and the same for `dec`
+        "inc" | "dec" => {
+            let (name, by) = rest.split_once(char::is_whitespace).unwrap_or((rest, "1"));
+            let by = by.trim().parse::<i64>().map_err(|_| (n, format!("`{}` isn't a whole number", by.trim())))?;
+            let name = scope.resolve_typed(name.trim(), &Value::Int(by)).map_err(|e| (n, e))?;
+            match keyword {
+                "inc" => Effect::Inc(name, by),
+                _ => Effect::Dec(name, by),
+            }
+        }
+        "give"  => { refs.items.push((n, arg.clone())); Effect::Give(arg) }
+        "take"  => { refs.items.push((n, arg.clone())); Effect::Take(arg) }
+        _       => { refs.rooms.push((n, arg.clone())); Effect::Go(arg) }
+    }))
+}
+
+// Parse text that may be guarded by conditions: `<text>` or `if <conditions>: <text>`.
+fn parse_guarded(rest: &str, n: usize, keyword: &str, refs: &mut Refs, scope: &Scope) -> Result<(Vec<Cond>, String), ParseError> {
+    match rest.strip_prefix("if ") {
+        Some(cond) => {
+            let (cond, msg) = cond.split_once(':').ok_or((n, format!("expected `{} if <conditions>: <text>`", keyword)))?;
+            Ok((parse_conds(cond, n, refs, scope).map_err(|e| (n, e))?, String::from(msg.trim())))
+        }
+        None => Ok((vec![], String::from(rest))),
+    }
+}
+
+// Parse one of the directives that can appear inside a `room` block.
+fn room_directive(r: &mut Room, keyword: &str, rest: &str, n: usize, refs: &mut Refs, scope: &Scope) -> Result<(), ParseError> {
+    match keyword {
+        "name" if !rest.is_empty() => r.name = String::from(rest),
+        "text" => {
+            let (conds, text) = parse_guarded(rest, n, keyword, refs, scope)?;
+            r.text.push((conds, Template::parse(&text, n, refs, scope).map_err(|e| (n, e))?));
+        }
+        "thing" => {
+            let usage = (n, String::from("expected `thing <name> [if <conditions>]: <description>`"));
+            let (head, desc) = rest.split_once(':').ok_or(usage.clone())?;
+            let (name, conds) = head.split_once(" if ").unwrap_or((head, ""));
+            if name.trim().is_empty() {
+                return Err(usage);
+            }
+            r.things.push(Thing {
+                name: String::from(name.trim()),
+                conds: parse_conds(conds, n, refs, scope).map_err(|e| (n, e))?,
+                desc: String::from(desc.trim()),
+            });
+        }
+        "contains" => {
+            refs.items.push((n, String::from(rest)));
+            refs.placed.push((n, String::from(rest)));
+            r.items.push(String::from(rest));
+        }
+        "exit" => {
+            let usage = (n, String::from("expected `exit <direction> <room> [through <door>] [if <conditions>[: <blocked message>]]`"));
+            let (dir, rest) = rest.split_once(char::is_whitespace).ok_or(usage.clone())?;
+            if !DIRECTIONS.contains(&dir) {
+                return Err((n, format!("unknown direction `{}`", dir)));
+            }
+            let (dest, gate) = rest.trim().split_once(char::is_whitespace).unwrap_or((rest.trim(), ""));
+            let (door, gate) = match gate.trim().strip_prefix("through ") {
+                Some(rest) => {
+                    let (door, gate) = rest.trim().split_once(char::is_whitespace).unwrap_or((rest.trim(), ""));
+                    refs.doors.push((n, String::from(door)));
+                    (Some(String::from(door)), gate)
+                }
+                None => (None, gate),
+            };
+            let (conds, blocked) = match gate.trim().strip_prefix("if ") {
+                Some(gate) => match gate.split_once(':') {
+                    Some((cond, msg)) => (cond, Some(String::from(msg.trim()))),
+                    None => (gate, None),
+                },
+                None if gate.trim().is_empty() => ("", None),
+                None => return Err(usage),
+            };
+            refs.rooms.push((n, String::from(dest)));
+            r.exits.push(Exit {
+                dir: String::from(dir),
+                dest: String::from(dest),
+                door,
+                conds: parse_conds(conds, n, refs, scope).map_err(|e| (n, e))?,
+                blocked,
+            });
+        }
+        "on" => {
+            let patterns = rest.split('|').map(Pattern::parse).collect::<Option<Vec<_>>>()
+                .ok_or((n, String::from("`on` needs actions like `get key` or `use key on chest`, separated by `|`")))?;
+            r.triggers.push(Trigger { patterns, cases: vec![] });
+        }
+        "case" => {
+            let trigger = r.triggers.last_mut().ok_or((n, String::from("`case` outside of an `on` block")))?;
+            trigger.cases.push(Case { conds: parse_conds(rest, n, refs, scope).map_err(|e| (n, e))?, effects: vec![] });
+        }
+        _ => match parse_effect(keyword, rest, n, refs, scope)? {
+            Some(effect) => {
+                let trigger = r.triggers.last_mut().ok_or((n, format!("`{}` outside of an `on` block", keyword)))?;
+                // Effects listed before any `case` form an unconditional case.
+                if trigger.cases.is_empty() {
+                    trigger.cases.push(Case { conds: vec![], effects: vec![] });
+                }
+                trigger.cases.last_mut().unwrap().effects.push(effect);
+            }
+            None => return Err((n, format!("unknown directive `{}`", keyword))),
+        },
+    }
+    Ok(())
+}
+
+impl World {
+    pub fn load(path: &str) -> Result<World, LoadError> {
+        let source = fs::read_to_string(path).map_err(|e| LoadError { path: String::from(path), line: 0, msg: e.to_string() })?;
+        World::parse(&source).map_err(|(line, msg)| LoadError { path: String::from(path), line, msg })
+    }
+
+    fn parse(source: &str) -> Result<World, ParseError> {
+        let mut start: Option<(usize, String)> = None;
+        let mut rooms: HashMap<String, Room> = HashMap::new();
+        let mut items: HashMap<String, Item> = HashMap::new();
+        let mut npcs: Vec<Npc> = vec![];
+        let mut vars: HashMap<String, Value> = HashMap::new();
+        let mut refs = Refs::default();
+        let mut capacity = Capacity::default();
+
+        let mut section = Section::Top;
+
+        for (i, line) in source.lines().enumerate() {
+            let n = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            // Variables declared in a room or NPC block are named after it.
+            let ns = match &section {
+                Section::Room(id) => Some(id.clone()),
+                Section::Npc(i) => Some(npcs[*i].id.clone()),
+                Section::Top | Section::Item(_) => None,
+            };
+
+            match keyword {
+                "start" => {
+                    if rest.is_empty() {
+                        return Err((n, String::from("`start` needs a room id")));
+                    }
+                    start = Some((n, String::from(rest)));
+                }
+                "max_items" => capacity.items = Some(rest.parse().map_err(|_| (n, format!("`{}` isn't a number of items", rest)))?),
+                "max_weight" => capacity.weight = Some(rest.parse().map_err(|_| (n, format!("`{}` isn't a weight", rest)))?),
+                "item" => {
+                    let usage = (n, String::from("expected `item <id>: <name>`"));
+                    let (id, name) = rest.split_once(':').ok_or(usage.clone())?;
+                    let (id, name) = (id.trim(), name.trim());
+                    if id.is_empty() || id.contains(char::is_whitespace) || name.is_empty() {
+                        return Err(usage);
+                    }
+                    // Rooms and containers share a namespace, as both are places items can be.
+                    if items.contains_key(id) || rooms.contains_key(id) {
+                        return Err((n, format!("`{}` is defined twice", id)));
+                    }
+                    items.insert(String::from(id), Item::new(id, name));
+                    section = Section::Item(String::from(id));
+                }
+                "room" => {
+                    if rest.is_empty() || rest.contains(char::is_whitespace) {
+                        return Err((n, String::from("expected `room <id>`")));
+                    }
+                    if rooms.contains_key(rest) || items.contains_key(rest) {
+                        return Err((n, format!("`{}` is defined twice", rest)));
+                    }
+                    // Rooms without a `name` are called after their id: `test_room` is "Test room".
+                    let mut name: String = rest.replace('_', " ");
+                    if let Some(first) = name.get_mut(..1) {
+                        first.make_ascii_uppercase();
+                    }
+                    rooms.insert(String::from(rest), Room { name, text: vec![], exits: vec![], things: vec![], items: vec![], npcs: vec![], triggers: vec![] });
+                    section = Section::Room(String::from(rest));
+                }
+                "npc" => {
+                    let (id, name) = rest.split_once(':').ok_or((n, String::from("expected `npc <id>: <name>`")))?;
+                    let id = id.trim();
+                    if id.is_empty() || id.contains(char::is_whitespace) {
+                        return Err((n, String::from("expected `npc <id>: <name>`")));
+                    }
+                    if npcs.iter().any(|npc| npc.id == id) {
+                        return Err((n, format!("npc `{}` is defined twice", id)));
+                    }
+                    npcs.push(Npc::new(id, name.trim()));
+                    section = Section::Npc(npcs.len() - 1);
+                }
+                // `flag <name>` or `var <name> = <value>`
+                "flag" | "var" => {
+                    let (name, val) = match (keyword, rest.split_once('=')) {
+                        ("flag", None) => (rest, Value::Bool(false)),
+                        ("var", Some((name, val))) if !val.trim().is_empty() => (name.trim(), Value::parse(val.trim())),
+                        _ => return Err((n, String::from("expected `flag <name>` or `var <name> = <value>`"))),
+                    };
+                    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '.') {
+                        return Err((n, format!("`{}` isn't a valid variable name", name)));
+                    }
+                    let name = match &ns {
+                        Some(ns) => format!("{}.{}", ns, name),
+                        None => String::from(name),
+                    };
+                    if vars.insert(name.clone(), val).is_some() {
+                        return Err((n, format!("`{}` is declared twice", name)));
+                    }
+                }
+                // Everything else belongs to the room or NPC currently being defined.
+                _ => match &section {
+                    Section::Item(id) => items.get_mut(id).unwrap().directive(keyword, rest, n, &mut refs, &mut vars)?,
+                    Section::Room(id) => {
+                        let scope = Scope { vars: &vars, ns: ns.as_deref() };
+                        room_directive(rooms.get_mut(id).unwrap(), keyword, rest, n, &mut refs, &scope)?
+                    }
+                    Section::Npc(i) => {
+                        let scope = Scope { vars: &vars, ns: ns.as_deref() };
+                        npcs[*i].directive(keyword, rest, n, &mut refs, &scope)?
+                    }
+                    Section::Top => return Err((n, format!("`{}` outside of a room", keyword))),
+                },
+            }
+        }
+
+        for (n, id) in &refs.rooms {
+            if !rooms.contains_key(id) {
+                return Err((*n, format!("no room named `{}`", id)));
+            }
+        }
+        for (n, id) in &refs.items {
+            if !items.contains_key(id) {
+                return Err((*n, format!("no item named `{}`", id)));
+            }
+        }
+        for (n, id) in &refs.doors {
+            match items.get(id) {
+                None => return Err((*n, format!("no item named `{}`", id))),
+                Some(door) if !door.openable => return Err((*n, format!("`{}` can't be a door unless it opens", id))),
+                Some(_) => {}
+            }
+        }
+        // Every item can only be in one place at a time.
+        let mut placed: Vec<&String> = vec![];
+        for (n, id) in &refs.placed {
+            if placed.contains(&id) {
+                return Err((*n, format!("item `{}` is placed more than once", id)));
+            }
+            placed.push(id);
+        }
+        // Nor can it end up inside itself. Since nothing is placed twice, this is the only way
+        // containers can loop.
+        for (n, item) in &refs.placed {
+            let mut inside = items[item].contents.clone();
+            while let Some(id) = inside.pop() {
+                if id == *item {
+                    return Err((*n, format!("item `{}` ends up inside itself", id)));
+                }
+                inside.extend(items[&id].contents.iter().cloned());
+            }
+        }
+        for (i, npc) in npcs.iter().enumerate() {
+            npc.validate()?;
+            if let Some(room) = &npc.room {
+                rooms.get_mut(room).unwrap().npcs.push(i);
+            }
+        }
+        let start = match start {
+            Some((n, id)) if !rooms.contains_key(&id) => return Err((n, format!("no room named `{}`", id))),
+            Some((_, id)) => id,
+            None => return Err((0, String::from("missing `start` directive"))),
+        };
+
+        Ok(World { start, rooms, items, npcs, vars, capacity })
+    }
+
+    // The state variables as they are at the start of a game.
+    pub fn initial_flags(&self) -> Flags {
+        let mut flags = Flags::new();
+        for (name, val) in &self.vars {
+            flags.set_value(name, val.clone());
+        }
+        flags
+    }
+
+    // Whether `var` is one of the world's declared state variables, by its full name.
+    pub fn declares(&self, var: &str) -> bool {
+        self.vars.contains_key(var)
+    }
+
+    // Every place items can be: the rooms, and the containers.
+    pub fn places(&self) -> impl Iterator<Item = &String> {
+        self.rooms.keys().chain(self.items.values().filter(|item| item.container).map(|item| &item.id))
+    }
+
+    // What's in each room and container at the start of a game, keyed by room or container id.
+    pub fn initial_contents(&self) -> HashMap<String, Inventory> {
+        self.places().map(|id| {
+            let start = match self.rooms.get(id) {
+                Some(room) => &room.items,
+                None => &self.item(id).contents,
+            };
+            let mut here = Inventory::new();
+            for item in start {
+                here.add(item);
+            }
+            (id.clone(), here)
+        }).collect()
+    }
+
+    // Print the room's description, including the items lying in it and any exits the player can see.
+    pub fn describe(&self, state: &State, detail: Detail, console: &mut dyn Console) {
+        let State { room: id, inv, flags, contents, visited } = state;
+        let (room, here) = (&self.rooms[id], &contents[id]);
+        match detail {
+            Detail::Full => for (_, text) in room.text.iter().filter(|(conds, _)| all_hold(conds, inv, flags)) {
+                // Lines whose template leaves nothing to say are left out altogether.
+                let text = text.render(inv, flags, !visited.contains(id));
+                if !text.trim().is_empty() {
+                    console.println(&text);
+                }
+            },
+            Detail::Short => console.println(&room.name),
+            Detail::Name => return console.println(&room.name),
+        }
+        // Scenery is left to the room's text to mention.
+        let loose: Vec<String> = here.items.iter().filter(|item| self.item(item).takeable).cloned().collect();
+        if !loose.is_empty() {
+            console.println(&format!("You see here: {}.", self.names(&loose).join(", ")));
+        }
+        for container in self.with_contents(&here.items, contents, flags) {
+            let inside = &contents.get(&container).map_or(&[][..], |c| &c.items[..]);
+            if !inside.is_empty() && self.is_open(&container, flags) {
+                console.println(&format!("In the {} you see: {}.", self.item(&container).name, self.names(inside).join(", ")));
+            }
+        }
+        let mut exits: Vec<&str> = vec![];
+        for exit in &room.exits {
+            if (exit.blocked.is_some() || all_hold(&exit.conds, inv, flags)) && !exits.contains(&exit.dir.as_str()) {
+                exits.push(&exit.dir);
+            }
+        }
+        if !exits.is_empty() {
+            console.println(&format!("Exits: {}.", exits.join(", ")));
+        }
+        for &i in &room.npcs {
+            console.println(&format!("{} is here.", self.npcs[i].name));
+        }
+    }
+
+    // Carry out a command that's specific to this room: movement, the room's own triggers,
+    // and everything to do with the things in it.
+    // Anything the command refers to is remembered in `referents` for "it" and "them".
+    // `queue` holds the commands still to run from the player's last line, for anything that
+    // wants to read ahead, like a conversation.
+    pub fn handle(&self, state: &mut State, input: &ParsedInput, referents: &mut Referents, queue: &mut VecDeque<ParsedInput>, console: &mut dyn Console) -> Transition {
+        let State { room: id, inv, flags, contents, .. } = state;
+        let room = &self.rooms[id.as_str()];
+        if let Some(dir) = input.direction() {
+            let exits = room.exits.iter().filter(|e| e.dir == dir);
+            if let Some(exit) = exits.clone().find(|e| all_hold(&e.conds, inv, flags)) {
+                match &exit.door {
+                    Some(door) if self.is_locked(door, flags) => console.println(&format!("The {} is locked.", self.item(door).name)),
+                    Some(door) if !self.is_open(door, flags) => console.println(&format!("The {} is closed.", self.item(door).name)),
+                    _ => return Transition::GoTo(exit.dest.clone()),
+                }
+                return Transition::Failed;
+            }
+            match exits.filter_map(|e| e.blocked.as_ref()).next() {
+                Some(msg) => console.println(msg),
+                None => console.println("You can't go that way."),
+            }
+            return Transition::Failed;
+        }
+
+        // What's around the player, and what they're carrying.
+        let around = self.around(id, contents, flags);
+        let carried = self.with_contents(&inv.items, contents, flags);
+        let reachable: Vec<String> = around.iter().chain(&carried).cloned().collect();
+
+        // Swap whatever the player called the item they're using for its real name, so
+        // triggers like `use key` work however the key was referred to.
+        let mut used = None;
+        let resolved;
+        let input = match input {
+            ParsedInput::Use(item) | ParsedInput::UseOn(item, _) => {
+                match self.resolve(item, &reachable) {
+                    Resolution::Found(item) => {
+                        let name = self.item(&item).name.clone();
+                        referents.remember(std::slice::from_ref(&name));
+                        resolved = match input {
+                            ParsedInput::UseOn(_, target) => ParsedInput::UseOn(name.to_lowercase(), target.clone()),
+                            _ => ParsedInput::Use(name.to_lowercase()),
+                        };
+                        used = Some(item);
+                        &resolved
+                    }
+                    Resolution::Ambiguous(items) => {
+                        self.ask_which(&items, console);
+                        return Transition::Failed;
+                    }
+                    Resolution::NotFound => input,
+                }
+            }
+            _ => input,
+        };
+
+        if let Some(transition) = self.fire_trigger(room, input, inv, contents, flags, console) {
+            return transition;
+        }
+
+        let npc = |target: &str| room.npcs.iter().map(|&i| &self.npcs[i]).find(|npc| mentions(&npc.name, target));
+        match input {
+            ParsedInput::Look(target) => {
+                if let Some(thing) = room.things.iter().find(|t| mentions(&t.name, target) && all_hold(&t.conds, inv, flags)) {
+                    referents.remember(std::slice::from_ref(&thing.name));
+                    console.println(&thing.desc);
+                } else if let Some(npc) = npc(target) {
+                    console.println(&npc.desc);
+                } else {
+                    match self.resolve(target, &reachable) {
+                        Resolution::Found(item) => {
+                            self.describe_item(&item, contents, flags, console);
+                            referents.remember(&self.names(&[item]));
+                        }
+                        Resolution::Ambiguous(items) => {
+                            self.ask_which(&items, console);
+                            return Transition::Failed;
+                        }
+                        Resolution::NotFound => {
+                            console.println("You don't see that here.");
+                            return Transition::Failed;
+                        }
+                    }
+                }
+            }
+            ParsedInput::Get(target) => {
+                // `all` only means what's lying around loose, not what's inside things.
+                let loose: Vec<String> = contents[id.as_str()].items.iter().filter(|item| self.item(item).takeable).cloned().collect();
+                let Some(items) = self.select(target, &around, &loose, "You don't see that here.", console) else {
+                    return Transition::Failed;
+                };
+                if items.is_empty() {
+                    console.println(if loose.is_empty() { "There's nothing here to take." } else { "There's nothing else to take." });
+                    return Transition::Failed;
+                }
+                return self.take(&items, inv, contents, flags, referents, console);
+            }
+            ParsedInput::Drop(target) => {
+                let Some(items) = self.select(target, &inv.items, &inv.items, "You aren't carrying that.", console) else {
+                    return Transition::Failed;
+                };
+                if items.is_empty() {
+                    console.println(if inv.items.is_empty() { "You aren't carrying anything." } else { "There's nothing else to drop." });
+                    return Transition::Failed;
+                }
+                for item in &items {
+                    inv.remove(item);
+                    contents.get_mut(id.as_str()).unwrap().add(item);
+                    match items.len() {
+                        1 => console.println(&format!("You drop the {}.", self.item(item).name)),
+                        _ => console.println(&format!("{}: Dropped.", self.item(item).name)),
+                    }
+                }
+                referents.remember(&self.names(&items));
+            }
+            ParsedInput::GetFrom(object, target) => {
+                let Some(container) = self.open_container(target, &reachable, flags, "There's nothing in that.", console) else {
+                    return Transition::Failed;
+                };
+                let inside = contents[&container].items.clone();
+                let Some(items) = self.select(object, &inside, &inside, "There's nothing like that in there.", console) else {
+                    return Transition::Failed;
+                };
+                if items.is_empty() {
+                    console.println(&format!("There's nothing in the {}.", self.item(&container).name));
+                    return Transition::Failed;
+                }
+                return self.take(&items, inv, contents, flags, referents, console);
+            }
+            ParsedInput::PutIn(object, target) => {
+                let Some(items) = self.select(object, &inv.items, &inv.items, "You aren't carrying that.", console) else {
+                    return Transition::Failed;
+                };
+                let Some(container) = self.open_container(target, &reachable, flags, "You can't put things in that.", console) else {
+                    return Transition::Failed;
+                };
+                if items.is_empty() {
+                    console.println("You aren't carrying anything.");
+                    return Transition::Failed;
+                }
+                for item in &items {
+                    let name = &self.item(item).name;
+                    if self.with_contents(std::slice::from_ref(item), contents, flags).contains(&container) {
+                        console.println(&format!("You can't put the {} inside itself.", name));
+                        continue;
+                    }
+                    inv.remove(item);
+                    contents.get_mut(&container).unwrap().add(item);
+                    match items.len() {
+                        1 => console.println(&format!("You put the {} in the {}.", name, self.item(&container).name)),
+                        _ => console.println(&format!("{}: Done.", name)),
+                    }
+                }
+                referents.remember(&self.names(&items));
+            }
+            ParsedInput::Use(_) | ParsedInput::UseOn(..) => {
+                let Some(key) = used.filter(|item| carried.contains(item)) else {
+                    console.println("You aren't carrying that.");
+                    return Transition::Failed;
+                };
+                let lock = match input {
+                    ParsedInput::UseOn(_, target) => match self.find(target, &reachable, "You don't see that here.", console) {
+                        Some(lock) => Some(lock),
+                        None => return Transition::Failed,
+                    },
+                    // Used on nothing in particular, a key unlocks whatever it fits. Only what's
+                    // actually locked counts, unless nothing it fits is locked.
+                    _ => {
+                        let fits: Vec<String> = reachable.iter().filter(|item| self.item(item).locked_by.as_ref() == Some(&key)).cloned().collect();
+                        let locked: Vec<String> = fits.iter().filter(|item| self.is_locked(item, flags)).cloned().collect();
+                        match if locked.is_empty() { fits } else { locked }.as_slice() {
+                            [] => None,
+                            [lock] => Some(lock.clone()),
+                            locks => {
+                                self.ask_which(locks, console);
+                                return Transition::Failed;
+                            }
+                        }
+                    }
+                };
+                match lock {
+                    Some(lock) if self.item(&lock).locked_by.is_some() => {
+                        referents.remember(&self.names(std::slice::from_ref(&lock)));
+                        return self.turn_key(&lock, &key, false, flags, console);
+                    }
+                    _ => {
+                        console.println("Nothing happens.");
+                        return Transition::Failed;
+                    }
+                }
+            }
+            ParsedInput::Open(target) | ParsedInput::Close(target) => {
+                let Some(item) = self.find(target, &reachable, "You don't see that here.", console) else {
+                    return Transition::Failed;
+                };
+                referents.remember(&self.names(std::slice::from_ref(&item)));
+                return match input {
+                    ParsedInput::Open(_) => self.open(&item, contents, flags, console),
+                    _ => self.close(&item, flags, console),
+                };
+            }
+            ParsedInput::Lock(target, key) | ParsedInput::Unlock(target, key) => {
+                let locking = matches!(input, ParsedInput::Lock(..));
+                let Some(lock) = self.find(target, &reachable, "You don't see that here.", console) else {
+                    return Transition::Failed;
+                };
+                referents.remember(&self.names(std::slice::from_ref(&lock)));
+                let item = self.item(&lock);
+                let verb = if locking { "lock" } else { "unlock" };
+                let Some(fits) = &item.locked_by else {
+                    console.println(&format!("You can't {} the {}.", verb, item.name));
+                    return Transition::Failed;
+                };
+                let key = match key.as_str() {
+                    // Without being told what with, the player uses the right key if they have it.
+                    "" if carried.contains(fits) => fits.clone(),
+                    "" => {
+                        console.println(&format!("You don't have anything to {} the {} with.", verb, item.name));
+                        return Transition::Failed;
+                    }
+                    key => match self.find(key, &carried, "You aren't carrying that.", console) {
+                        Some(key) => key,
+                        None => return Transition::Failed,
+                    },
+                };
+                return self.turn_key(&lock, &key, locking, flags, console);
+            }
+            ParsedInput::Talk(target) => match npc(target) {
+                Some(npc) => return self.converse(npc, inv, contents, flags, queue, console),
+                None => {
+                    console.println("There's nobody here by that name.");
+                    return Transition::Failed;
+                }
+            },
+            ParsedInput::Other(text) => {
+                self.not_understood(text, room, &reachable, console);
+                return Transition::Failed;
+            }
+            _ => {}
+        }
+        Transition::Stay
+    }
+
+    // Move `items` into the player's inventory from wherever they are, apart from any that
+    // can't be picked up or won't fit in the player's hands.
+    fn take(&self, items: &[String], inv: &mut Inventory, contents: &mut HashMap<String, Inventory>, flags: &Flags, referents: &mut Referents, console: &mut dyn Console) -> Transition {
+        let mut taken = false;
+        for id in items {
+            let name = &self.item(id).name;
+            if !self.item(id).takeable {
+                match items.len() {
+                    1 => console.println(&format!("You can't take the {}.", name)),
+                    _ => console.println(&format!("{}: You can't take that.", name)),
+                }
+                continue;
+            }
+            if !self.can_carry(id, inv, contents, flags) {
+                match items.len() {
+                    1 => console.println("You're carrying too much."),
+                    _ => console.println(&format!("{}: You're carrying too much.", name)),
+                }
+                continue;
+            }
+            for place in contents.values_mut() {
+                if place.remove(id) {
+                    break;
+                }
+            }
+            inv.add(id);
+            taken = true;
+            match items.len() {
+                1 => console.println(&format!("You pick up the {}.", name)),
+                _ => console.println(&format!("{}: Taken.", name)),
+            }
+        }
+        referents.remember(&self.names(items));
+        if taken { Transition::Stay } else { Transition::Failed }
+    }
+
+    // The one item among `candidates` that the player means by `text`. Says why not otherwise,
+    // with `missing` if there's nothing like it.
+    fn find(&self, text: &str, candidates: &[String], missing: &str, console: &mut dyn Console) -> Option<String> {
+        match self.resolve(text, candidates) {
+            Resolution::Found(id) => return Some(id),
+            Resolution::Ambiguous(items) => self.ask_which(&items, console),
+            Resolution::NotFound => console.println(missing),
+        }
+        None
+    }
+
+    // Find the container the player means by `text`, as long as it's open. Says why not otherwise.
+    fn open_container(&self, text: &str, reachable: &[String], flags: &Flags, not_container: &str, console: &mut dyn Console) -> Option<String> {
+        let id = self.find(text, reachable, "You don't see that here.", console)?;
+        if !self.item(&id).container {
+            console.println(not_container);
+        } else if !self.is_open(&id, flags) {
+            console.println(&format!("The {} is closed.", self.item(&id).name));
+        } else {
+            return Some(id);
+        }
+        None
+    }
+
+    // Work out which of the items in `from` a phrase like "key", "key and sword", "all" or
+    // "all except sword" picks out, where "all" means everything in `everything`. Returns
+    // None, having said why, if that can't be done.
+    fn select(&self, phrase: &str, from: &[String], everything: &[String], missing: &str, console: &mut dyn Console) -> Option<Vec<String>> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        let (all, list) = match words.as_slice() {
+            ["all" | "everything"] => (true, String::new()),
+            ["all" | "everything", "except" | "but", rest @ ..] => (true, rest.join(" ")),
+            _ => (false, String::from(phrase)),
+        };
+
+        let mut chosen: Vec<String> = vec![];
+        for part in list.split(',').flat_map(|p| p.split(" and ")).map(str::trim).filter(|p| !p.is_empty()) {
+            match self.resolve(part, from) {
+                Resolution::Found(item) if !chosen.contains(&item) => chosen.push(item),
+                Resolution::Found(_) => {}
+                Resolution::Ambiguous(items) => {
+                    self.ask_which(&items, console);
+                    return None;
+                }
+                // Excepting something that isn't there anyway is fine.
+                Resolution::NotFound if all => {}
+                Resolution::NotFound => {
+                    console.println(missing);
+                    return None;
+                }
+            }
+        }
+
+        if all {
+            // Everything, apart from the exceptions.
+            chosen = everything.iter().filter(|item| !chosen.contains(item)).cloned().collect();
+        } else if chosen.is_empty() {
+            console.println(missing);
+            return None;
+        }
+        Some(chosen)
+    }
+
+    // Run the room's reaction to the input, if it has one whose conditions hold.
+    fn fire_trigger(&self, room: &Room, input: &ParsedInput, inv: &mut Inventory, contents: &mut HashMap<String, Inventory>, flags: &mut Flags, console: &mut dyn Console) -> Option<Transition> {
+        let trigger = room.triggers.iter().find(|t| t.patterns.iter().any(|p| p.matches(input)))?;
+        let case = trigger.cases.iter().find(|c| all_hold(&c.conds, inv, flags))?;
+        Some(self.apply(&case.effects, inv, contents, flags, console))
+    }
+
+    fn apply(&self, effects: &[Effect], inv: &mut Inventory, contents: &mut HashMap<String, Inventory>, flags: &mut Flags, console: &mut dyn Console) -> Transition {
+        let mut next = Transition::Stay;
+        for effect in effects {
+            match effect {
+                Effect::Say(msg)   => console.println(msg),
+                Effect::Set(var, val) => flags.set_value(var, val.clone()),
+                Effect::Inc(var, by) => flags.inc(var, *by),
+                Effect::Dec(var, by) => flags.dec(var, *by),
+                // Items are only ever in one place, so a given item leaves wherever it was.
+                Effect::Give(item) if !inv.has(item) => {
+                    for place in contents.values_mut() {
+                        place.remove(item);
+                    }
+                    inv.add(item);
+                }
+                Effect::Give(_) => {}
+                Effect::Take(item) => { inv.remove(item); }
+                Effect::Go(dest)   => next = Transition::GoTo(dest.clone()),
+            }
+        }
+        next
+    }
+
+    fn ask_which(&self, items: &[String], console: &mut dyn Console) {
+        console.println(&format!("Which do you mean: {}?", list_choices(&self.names(items))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::{console::Scripted, Flags, Inventory, Value};
+    use crate::parser::parse_input;
+    use super::{all_hold, parse_conds, Pattern, Refs, Scope, World};
+
+    fn error(source: &str) -> (usize, String) {
+        World::parse(source).err().expect("the world should have been rejected")
+    }
+
+    #[test]
+    fn loads_the_test_world() {
+        World::load(concat!(env!("CARGO_MANIFEST_DIR"), "/worlds/test.world")).unwrap();
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        assert_eq!(error("start r\nroom r\n    exit north nowhere\n"), (3, String::from("no room named `nowhere`")));
+        assert_eq!(error("start r\n\nexit north r\n"), (3, String::from("`exit` outside of a room")));
+        assert_eq!(error("start r\nroom r\n    sparkle\n"), (3, String::from("unknown directive `sparkle`")));
+        assert_eq!(error("room r\n"), (0, String::from("missing `start` directive")));
+        assert_eq!(error("start r\nroom r\n    text if lamp_lit: Bright.\n").0, 3);
+        assert_eq!(error("start r\nroom r\n    text hi {if has:Golden Key}x{end}\n"), (3, String::from("no item named `Golden Key`")));
+        assert_eq!(error("start r\nroom r\n    exit north r if has lamp\n"), (3, String::from("no item named `lamp`")));
+    }
+
+    #[test]
+    fn all_except() {
+        let world = World::parse("start r\nitem sword: Sword\nitem lamp: Lamp\nitem rag: Oily Rag\nroom r\n").unwrap();
+        let items: Vec<String> = ["sword", "lamp", "rag"].iter().map(|id| String::from(*id)).collect();
+        let mut console = Scripted::new(vec![]);
+        let mut select = |phrase: &str| world.select(phrase, &items, &items, "missing", &mut console);
+        assert_eq!(select("all"), Some(items.clone()));
+        assert_eq!(select("all except sword and rag"), Some(vec![String::from("lamp")]));
+        assert_eq!(select("lamp, sword"), Some(vec![String::from("lamp"), String::from("sword")]));
+        assert_eq!(select("all but the crown"), Some(items.clone()));
+        assert_eq!(select("crown"), None);
+    }
+
+    #[test]
+    fn rejects_impossible_items() {
+        assert_eq!(error("start r\nitem k: Key\nroom r\n    contains k\nroom s\n    contains k\n"),
+                   (6, String::from("item `k` is placed more than once")));
+        assert!(error("start r\nitem k: Key\nroom r\n    exit north r through k\n").1.contains("can't be a door"));
+        assert_eq!(error("start r\nitem k: Key\n    container\n    contains k\nroom r\n"), (4, String::from("item `k` ends up inside itself")));
+        assert_eq!(error("start r\nitem a: A\n    container\n    contains b\nitem b: B\n    container\n    contains a\nroom r\n"),
+                   (4, String::from("item `b` ends up inside itself")));
+    }
+
+    #[test]
+    fn comparisons() {
+        let inv = Inventory::new();
+        let mut flags = Flags::new();
+        let vars = HashMap::from([(String::from("visits"), Value::Int(0)), (String::from("mood"), Value::Str(String::new()))]);
+        let scope = Scope { vars: &vars, ns: None };
+        let conds = |s: &str| parse_conds(s, 1, &mut Refs::default(), &scope);
+        let holds = |s: &str, flags: &Flags| all_hold(&conds(s).unwrap(), &inv, flags);
+        // Unset variables count as 0, false or empty.
+        assert!(holds("visits == 0, mood != angry", &flags));
+        flags.set_int("visits", 3);
+        flags.set_value("mood", Value::Str(String::from("angry")));
+        assert!(holds("visits >= 3, visits < 10, mood == angry", &flags));
+        assert!(!holds("visits > 3", &flags));
+        // Different types never compare equal.
+        assert!(!holds("visits == \"3\"", &flags));
+        assert!(holds("visits != true", &flags));
+        assert!(conds("visits >=").is_err());
+        assert!(conds("visitors > 1").is_err());
+    }
+
+    #[test]
+    fn effects_fit_the_declared_types() {
+        let world = |effect: &str| World::parse(&format!("start r\nflag met\nvar visits = 0\nroom r\n    on wait\n        {}\n", effect));
+        for effect in ["set met", "clear met", "set visits = 5", "inc visits", "dec visits 2"] {
+            assert!(world(effect).is_ok(), "`{}` should be allowed", effect);
+        }
+        assert_eq!(world("inc met").err(), Some((6, String::from("`met` holds a flag, not a number like `1`"))));
+        assert_eq!(world("set visits = hello").err(), Some((6, String::from("`visits` holds a number, not a string like `hello`"))));
+        assert!(world("set visits").is_err());
+        assert!(world("set met = 3").is_err());
+    }
+
+    #[test]
+    fn patterns_match_whole_words() {
+        let pattern = Pattern::parse("use key on chest").unwrap();
+        let matches = |s: &str| pattern.matches(&parse_input(String::from(s)));
+        assert!(matches("use the golden key on the chest"));
+        assert!(matches("use key, on chest"));
+        assert!(!matches("use monkey on chest"));
+        assert!(!matches("use key on chestnut"));
+        assert!(!matches("use key"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, VecDeque};
+use crate::{console::Console, game::Transition, get_user_input, Flags, Inventory, ParsedInput};
+use super::{all_hold, parse_conds, parse_effect, parse_guarded, Cond, Effect, ParseError, Refs, Scope, World};
+
+// Characters and their conversations.
+// An NPC is declared with `npc <id>: <name>` and owns a tree of dialogue nodes. Talking to
+// them starts at their first node: the node's lines are spoken, its effects are applied, and
+// then the player picks one of its numbered choices to move to another node. A node with no
+// available choices, or a choice leading to `end`, finishes the conversation.
+
+struct Choice {
+    line: usize,
+    conds: Vec<Cond>,
+    text: String,
+    next: String,
+}
+
+struct Node {
+    id: String,
+    lines: Vec<(Vec<Cond>, String)>,
+    effects: Vec<Effect>,
+    choices: Vec<Choice>,
+}
+
+pub struct Npc {
+    pub(super) id: String,
+    pub(super) name: String,
+    pub(super) desc: String,
+    pub(super) room: Option<String>,
+    nodes: Vec<Node>,
+}
+
+impl Npc {
+    pub(super) fn new(id: &str, name: &str) -> Npc {
+        Npc {
+            id: String::from(id),
+            name: String::from(name),
+            desc: format!("You see nothing special about {}.", name),
+            room: None,
+            nodes: vec![],
+        }
+    }
+
+    // Parse one of the directives that can appear inside an `npc` block.
+    pub(super) fn directive(&mut self, keyword: &str, rest: &str, n: usize, refs: &mut Refs, scope: &Scope) -> Result<(), ParseError> {
+        match keyword {
+            "in" => {
+                refs.rooms.push((n, String::from(rest)));
+                self.room = Some(String::from(rest));
+            }
+            "desc" => self.desc = String::from(rest),
+            "node" => {
+                if rest.is_empty() || rest.contains(char::is_whitespace) {
+                    return Err((n, String::from("expected `node <id>`")));
+                }
+                if rest == "end" || self.nodes.iter().any(|node| node.id == rest) {
+                    return Err((n, format!("node `{}` is defined twice", rest)));
+                }
+                self.nodes.push(Node { id: String::from(rest), lines: vec![], effects: vec![], choices: vec![] });
+            }
+            "line" => {
+                let line = parse_guarded(rest, n, keyword, refs, scope)?;
+                self.node(n, keyword)?.lines.push(line);
+            }
+            "choice" => {
+                let usage = (n, String::from("expected `choice [if <conditions>:] <text> -> <node>`"));
+                let (head, next) = rest.rsplit_once("->").ok_or(usage.clone())?;
+                let (conds, text) = match head.trim().strip_prefix("if ") {
+                    Some(guarded) => guarded.split_once(':').ok_or(usage.clone())?,
+                    None => ("", head),
+                };
+                if text.trim().is_empty() || next.trim().is_empty() {
+                    return Err(usage);
+                }
+                let choice = Choice {
+                    line: n,
+                    conds: parse_conds(conds, n, refs, scope).map_err(|e| (n, e))?,
+                    text: String::from(text.trim()),
+                    next: String::from(next.trim()),
+                };
+                self.node(n, keyword)?.choices.push(choice);
+            }
+            _ => match parse_effect(keyword, rest, n, refs, scope)? {
+                Some(effect) => self.node(n, keyword)?.effects.push(effect),
+                None => return Err((n, format!("unknown directive `{}`", keyword))),
+            },
+        }
+        Ok(())
+    }
+
+    // The node currently being defined.
+    fn node(&mut self, n: usize, keyword: &str) -> Result<&mut Node, ParseError> {
+        self.nodes.last_mut().ok_or((n, format!("`{}` outside of a `node`", keyword)))
+    }
+
+    // Check that every choice leads somewhere.
+    pub(super) fn validate(&self) -> Result<(), ParseError> {
+        for choice in self.nodes.iter().flat_map(|node| &node.choices) {
+            if choice.next != "end" && !self.nodes.iter().any(|node| node.id == choice.next) {
+                return Err((choice.line, format!("npc `{}` has no node named `{}`", self.id, choice.next)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl World {
+    // Hold a conversation with `npc` until it runs out of choices or the player walks away.
+    // Choices are taken from `queue` before any more input is read, so `talk to tom. 1` works.
+    // Anything other than a number ends the conversation and is left on the queue to run.
+    pub(super) fn converse(&self, npc: &Npc, inv: &mut Inventory, contents: &mut HashMap<String, Inventory>, flags: &mut Flags,
+                           queue: &mut VecDeque<ParsedInput>, console: &mut dyn Console) -> Transition {
+        let mut node = match npc.nodes.first() {
+            Some(node) => node,
+            None => {
+                console.println(&format!("{} has nothing to say.", npc.name));
+                return Transition::Stay;
+            }
+        };
+
+        loop {
+            for (conds, line) in &node.lines {
+                if all_hold(conds, inv, flags) {
+                    console.println(&format!("{}: \"{}\"", npc.name, line));
+                }
+            }
+            // Moving the player somewhere else ends the conversation.
+            if let Transition::GoTo(room) = self.apply(&node.effects, inv, contents, flags, console) {
+                return Transition::GoTo(room);
+            }
+
+            let choices: Vec<&Choice> = node.choices.iter().filter(|c| all_hold(&c.conds, inv, flags)).collect();
+            if choices.is_empty() {
+                return Transition::Stay;
+            }
+            for (i, choice) in choices.iter().enumerate() {
+                console.println(&format!("  {}. {}", i + 1, choice.text));
+            }
+            console.println("  0. (Leave)");
+
+            let choice = loop {
+                if queue.is_empty() {
+                    match get_user_input(console) {
+                        Ok(commands) => queue.extend(commands),
+                        Err(_) => return Transition::Quit,
+                    }
+                }
+                let number = match queue.front() {
+                    Some(ParsedInput::Other(text)) => match text.parse::<usize>() {
+                        Ok(number) => number,
+                        Err(_) => return Transition::Stay,
+                    },
+                    Some(_) => return Transition::Stay,
+                    // A blank line.
+                    None => continue,
+                };
+                queue.pop_front();
+                match number {
+                    0 => return Transition::Stay,
+                    i if i <= choices.len() => break choices[i - 1],
+                    _ => console.println(&format!("Pick a number from 0 to {}.", choices.len())),
+                }
+            };
+
+            match npc.nodes.iter().find(|node| node.id == choice.next) {
+                Some(next) => node = next,
+                None => return Transition::Stay,
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use crate::{console::Console, game::Transition, Flags, Inventory, Value};
+use super::{ParseError, Refs, World};
+
+// Items and what can be done with them.
+// An item is declared with `item <id>: <name>` and given a description and properties by the
+// directives after it. What's fixed about an item lives here; what changes as the game is
+// played lives in the game state: where each item is (an Inventory per room, per container
+// and for the player), and, for items that open, the flags `<id>.open` and `<id>.locked`,
+// which world files can test like any other flag.
+//
+// An item that opens can also stand in a doorway: an exit declared `through` it can only be
+// used while it's open, and it can be opened, closed, locked and unlocked from either side.
+
+// How much the player can carry, by number of items and by weight. Either can be left
+// unlimited. Only picking things up is limited; what the world gives the player always fits.
+#[derive(Default)]
+pub(super) struct Capacity {
+    pub(super) items: Option<usize>,
+    pub(super) weight: Option<u32>,
+}
+
+pub struct Item {
+    pub(super) id: String,
+    pub(super) name: String,
+    // Other names the player can use for the item.
+    pub(super) aliases: Vec<String>,
+    pub(super) desc: String,
+    pub(super) weight: u32,
+    // Whether the player can pick it up. Items that can't are scenery, which the room's text
+    // is expected to mention, so they aren't listed among what's lying around.
+    pub(super) takeable: bool,
+    pub(super) container: bool,
+    pub(super) openable: bool,
+    // The id of the item that locks and unlocks this one, if it has a lock.
+    pub(super) locked_by: Option<String>,
+    pub(super) lit: bool,
+    pub(super) wearable: bool,
+    // Ids of the items inside it when the game starts.
+    pub(super) contents: Vec<String>,
+}
+
+impl Item {
+    pub(super) fn new(id: &str, name: &str) -> Item {
+        Item {
+            id: String::from(id),
+            name: String::from(name),
+            aliases: vec![],
+            desc: format!("You see nothing special about the {}.", name),
+            weight: 1,
+            takeable: true,
+            container: false,
+            openable: false,
+            locked_by: None,
+            lit: false,
+            wearable: false,
+            contents: vec![],
+        }
+    }
+
+    // Parse one of the directives that can appear after an `item` declaration. Properties
+    // that change during play declare the flags that hold them in `vars`.
+    pub(super) fn directive(&mut self, keyword: &str, rest: &str, n: usize, refs: &mut Refs, vars: &mut HashMap<String, Value>) -> Result<(), ParseError> {
+        let (open, locked) = (format!("{}.open", self.id), format!("{}.locked", self.id));
+        match (keyword, rest) {
+            ("desc", _) => self.desc = String::from(rest),
+            ("alias", _) => self.aliases.extend(rest.split(',').map(str::trim).filter(|a| !a.is_empty()).map(String::from)),
+            ("weight", _) => self.weight = rest.parse().map_err(|_| (n, format!("`{}` isn't a weight", rest)))?,
+            ("fixed", "") => self.takeable = false,
+            ("container", "") => self.container = true,
+            ("openable", "") => {
+                self.openable = true;
+                vars.entry(open).or_insert(Value::Bool(false));
+            }
+            ("open", "") if vars.get(&locked) == Some(&Value::Bool(true)) => {
+                return Err((n, format!("`{}` can't start out both open and locked", self.id)));
+            }
+            ("open", "") => {
+                self.openable = true;
+                vars.insert(open, Value::Bool(true));
+            }
+            ("locked" | "lockable", _) => {
+                let key = rest.strip_prefix("by ").map(str::trim).filter(|k| !k.is_empty())
+                    .ok_or((n, format!("expected `{} by <item>`", keyword)))?;
+                refs.items.push((n, String::from(key)));
+                self.openable = true;
+                self.locked_by = Some(String::from(key));
+                if keyword == "locked" {
+                    if vars.get(&open) == Some(&Value::Bool(true)) {
+                        return Err((n, format!("`{}` can't start out both open and locked", self.id)));
+                    }
+                    vars.insert(open, Value::Bool(false));
+                    vars.insert(locked, Value::Bool(true));
+                } else {
+                    vars.entry(open).or_insert(Value::Bool(false));
+                    vars.entry(locked).or_insert(Value::Bool(false));
+                }
+            }
+            ("lit", "") => self.lit = true,
+            ("wearable", "") => self.wearable = true,
+            ("contains", _) if self.container => {
+                refs.items.push((n, String::from(rest)));
+                refs.placed.push((n, String::from(rest)));
+                self.contents.push(String::from(rest));
+            }
+            ("contains", _) => return Err((n, format!("`{}` has to be a `container` before it can contain anything", self.id))),
+            _ => return Err((n, format!("unknown directive `{}`", keyword))),
+        }
+        Ok(())
+    }
+}
+
+impl World {
+    pub(super) fn item(&self, id: &str) -> &Item {
+        &self.items[id]
+    }
+
+    pub fn has_item(&self, id: &str) -> bool {
+        self.items.contains_key(id)
+    }
+
+    // The display names of the items with these ids.
+    pub(super) fn names(&self, ids: &[String]) -> Vec<String> {
+        ids.iter().map(|id| self.item(id).name.clone()).collect()
+    }
+
+    pub(super) fn is_open(&self, id: &str, flags: &Flags) -> bool {
+        let item = self.item(id);
+        !item.openable || flags.is_set(&format!("{}.open", id))
+    }
+
+    pub(super) fn is_locked(&self, id: &str, flags: &Flags) -> bool {
+        flags.is_set(&format!("{}.locked", id))
+    }
+
+    // `items`, along with everything inside any of them that's an open container, and so on
+    // all the way down.
+    pub(super) fn with_contents(&self, items: &[String], contents: &HashMap<String, Inventory>, flags: &Flags) -> Vec<String> {
+        let mut all = vec![];
+        for id in items {
+            all.push(id.clone());
+            if self.item(id).container && self.is_open(id, flags) {
+                all.extend(self.with_contents(&contents[id].items, contents, flags));
+            }
+        }
+        all
+    }
+
+    // Print what the player sees when they look at an item.
+    pub(super) fn describe_item(&self, id: &str, contents: &HashMap<String, Inventory>, flags: &Flags, console: &mut dyn Console) {
+        let item = self.item(id);
+        console.println(&item.desc);
+        if self.is_locked(id, flags) {
+            console.println("It's locked.");
+        } else if item.openable {
+            console.println(if self.is_open(id, flags) { "It's open." } else { "It's closed." });
+        }
+        if item.container && self.is_open(id, flags) {
+            match self.names(&contents[id].items).as_slice() {
+                [] => console.println("It's empty."),
+                names => console.println(&format!("It contains: {}.", names.join(", "))),
+            }
+        }
+        if item.lit {
+            console.println("It's giving off light.");
+        }
+        if item.wearable {
+            console.println("You could wear it.");
+        }
+    }
+
+    // Everything the player can get at in `room` without carrying it: what's in the room,
+    // including inside open containers, and the doors leading out of it.
+    pub(super) fn around(&self, room: &str, contents: &HashMap<String, Inventory>, flags: &Flags) -> Vec<String> {
+        let mut around = self.with_contents(&contents[room].items, contents, flags);
+        for door in self.rooms[room].exits.iter().filter_map(|e| e.door.as_ref()) {
+            if !around.contains(door) {
+                around.push(door.clone());
+            }
+        }
+        around
+    }
+
+    pub(super) fn open(&self, id: &str, contents: &HashMap<String, Inventory>, flags: &mut Flags, console: &mut dyn Console) -> Transition {
+        let item = self.item(id);
+        if !item.openable {
+            console.println(&format!("You can't open the {}.", item.name));
+            return Transition::Failed;
+        }
+        if self.is_locked(id, flags) {
+            console.println(&format!("The {} is locked.", item.name));
+            return Transition::Failed;
+        }
+        if self.is_open(id, flags) {
+            console.println(&format!("The {} is already open.", item.name));
+            return Transition::Failed;
+        }
+        flags.set(&format!("{}.open", id));
+        console.println(&format!("You open the {}.", item.name));
+        if item.container {
+            match self.names(&contents[id].items).as_slice() {
+                [] => console.println("It's empty."),
+                names => console.println(&format!("Inside you find: {}.", names.join(", "))),
+            }
+        }
+        Transition::Stay
+    }
+
+    pub(super) fn close(&self, id: &str, flags: &mut Flags, console: &mut dyn Console) -> Transition {
+        let item = self.item(id);
+        if !item.openable {
+            console.println(&format!("You can't close the {}.", item.name));
+            return Transition::Failed;
+        }
+        if !self.is_open(id, flags) {
+            console.println(&format!("The {} is already closed.", item.name));
+            return Transition::Failed;
+        }
+        flags.set_as(&format!("{}.open", id), false);
+        console.println(&format!("You close the {}.", item.name));
+        Transition::Stay
+    }
+
+    // Lock (or unlock) `id` with `key`, as long as it's the right key.
+    pub(super) fn turn_key(&self, id: &str, key: &str, lock: bool, flags: &mut Flags, console: &mut dyn Console) -> Transition {
+        let (item, key) = (self.item(id), self.item(key));
+        let verb = if lock { "lock" } else { "unlock" };
+        match &item.locked_by {
+            None => console.println(&format!("You can't {} the {}.", verb, item.name)),
+            Some(fits) if *fits != key.id => console.println(&format!("The {} doesn't fit the {}.", key.name, item.name)),
+            Some(_) if lock && self.is_locked(id, flags) => console.println(&format!("The {} is already locked.", item.name)),
+            Some(_) if !lock && !self.is_locked(id, flags) => console.println(&format!("The {} isn't locked.", item.name)),
+            Some(_) if lock && self.is_open(id, flags) => console.println(&format!("You'll have to close the {} first.", item.name)),
+            Some(_) => {
+                flags.set_as(&format!("{}.locked", id), lock);
+                console.println(&format!("You {} the {} with the {}.", verb, item.name, key.name));
+                return Transition::Stay;
+            }
+        }
+        Transition::Failed
+    }
+
+    // How heavy an item is, along with everything inside it. Weights stop at u32::MAX rather
+    // than overflowing.
+    fn weight(&self, id: &str, contents: &HashMap<String, Inventory>) -> u32 {
+        let inside = contents.get(id).map_or(&[][..], |c| &c.items[..]);
+        self.total_weight(inside, contents).saturating_add(self.item(id).weight)
+    }
+
+    fn total_weight(&self, items: &[String], contents: &HashMap<String, Inventory>) -> u32 {
+        items.iter().fold(0, |total: u32, item| total.saturating_add(self.weight(item, contents)))
+    }
+
+    // Whether the player has room to pick up `id` on top of what they've already got.
+    pub(super) fn can_carry(&self, id: &str, inv: &Inventory, contents: &HashMap<String, Inventory>, flags: &Flags) -> bool {
+        // Taking something out of a bag the player's holding doesn't make them any heavier.
+        let held = self.with_contents(&inv.items, contents, flags).iter().any(|item| item == id);
+        let extra = if held { 0 } else { self.weight(id, contents) };
+        self.capacity.items.is_none_or(|max| inv.items.len() < max)
+            && self.capacity.weight.is_none_or(|max| self.total_weight(&inv.items, contents).saturating_add(extra) <= max)
+    }
+
+    // The player's inventory as a table of names, descriptions and weights, with totals.
+    pub fn show_inventory(&self, inv: &Inventory, contents: &HashMap<String, Inventory>) -> String {
+        let mut out = String::from("--- INVENTORY ---\n");
+        for id in &inv.items {
+            let item = self.item(id);
+            out += &format!("{: <10} | {: <10} | {}\n", item.name, item.desc, self.weight(id, contents));
+        }
+        let count = inv.items.len();
+        out += &format!("Total: {} item{}", count, if count == 1 { "" } else { "s" });
+        if let Some(max) = self.capacity.items {
+            out += &format!(" (max {})", max);
+        }
+        out += &format!(", weight {}", self.total_weight(&inv.items, contents));
+        if let Some(max) = self.capacity.weight {
+            out += &format!(" (max {})", max);
+        }
+        out += "\n";
+        out + "------------------\n"
+    }
+}
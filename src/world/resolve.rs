@@ -0,0 +1,128 @@
+use super::World;
+
+// Working out which item the player means.
+// Every word they type, apart from articles, has to be the start of a word in the item's
+// name or one of its aliases, ignoring case: "gold key", "key" and "golden" all find
+// "Golden Key". A name or alias typed out in full beats any partial match.
+
+pub enum Resolution {
+    Found(String),
+    Ambiguous(Vec<String>),
+    NotFound,
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Fit {
+    No,
+    Partial,
+    Exact,
+}
+
+// The words of the player's text that can actually pick something out.
+fn significant_words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(str::to_lowercase)
+        .filter(|w| !matches!(w.as_str(), "the" | "a" | "an"))
+        .collect()
+}
+
+fn fit(name: &str, words: &[String]) -> Fit {
+    let name = name.to_lowercase();
+    if words.is_empty() {
+        Fit::No
+    } else if words.join(" ") == name {
+        Fit::Exact
+    } else if words.iter().all(|w| name.split_whitespace().any(|n| n.starts_with(w.as_str()))) {
+        Fit::Partial
+    } else {
+        Fit::No
+    }
+}
+
+// Does the player's text refer to `name`? Used for things that have no aliases.
+pub(super) fn mentions(name: &str, text: &str) -> bool {
+    fit(name, &significant_words(text)) != Fit::No
+}
+
+// "the Golden Key", "the Golden Key or the Sword", "the A, the B or the C".
+pub fn list_choices(names: &[String]) -> String {
+    let names: Vec<String> = names.iter().map(|n| format!("the {}", n)).collect();
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+impl World {
+    // Work out which of the `candidates` (item ids) the player's text refers to.
+    pub(super) fn resolve<'a, I: IntoIterator<Item = &'a String>>(&self, text: &str, candidates: I) -> Resolution {
+        let words = significant_words(text);
+        let mut best = Fit::No;
+        let mut matches: Vec<String> = vec![];
+
+        for id in candidates {
+            let item = self.item(id);
+            let fit = item.aliases.iter().map(|a| fit(a, &words)).fold(fit(&item.name, &words), |a, b| if b > a { b } else { a });
+            if fit == Fit::No || fit < best {
+                continue;
+            }
+            if fit > best {
+                best = fit;
+                matches.clear();
+            }
+            if !matches.contains(id) {
+                matches.push(id.clone());
+            }
+        }
+
+        match matches.len() {
+            0 => Resolution::NotFound,
+            1 => Resolution::Found(matches.remove(0)),
+            _ => Resolution::Ambiguous(matches),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_choices, mentions, Resolution, World};
+
+    const WORLD: &str = "
+start r
+item golden_key: Golden Key
+    alias trinket
+item silver_key: Silver Key
+item key_ring: Key
+room r
+";
+
+    fn resolve(world: &World, text: &str) -> Resolution {
+        let ids: Vec<String> = ["golden_key", "silver_key", "key_ring"].iter().map(|id| String::from(*id)).collect();
+        world.resolve(text, &ids)
+    }
+
+    #[test]
+    fn partial_names_and_aliases() {
+        let world = World::parse(WORLD).unwrap();
+        assert!(matches!(resolve(&world, "gold"), Resolution::Found(id) if id == "golden_key"));
+        assert!(matches!(resolve(&world, "the silv k"), Resolution::Found(id) if id == "silver_key"));
+        assert!(matches!(resolve(&world, "Trinket"), Resolution::Found(id) if id == "golden_key"));
+        assert!(matches!(resolve(&world, "sword"), Resolution::NotFound));
+    }
+
+    #[test]
+    fn exact_names_beat_partial_ones() {
+        let world = World::parse(WORLD).unwrap();
+        assert!(matches!(resolve(&world, "key"), Resolution::Found(id) if id == "key_ring"));
+        assert!(matches!(resolve(&world, "k"), Resolution::Ambiguous(ids) if ids.len() == 3));
+    }
+
+    #[test]
+    fn mentions_and_choices() {
+        assert!(mentions("Old Tom", "tom"));
+        assert!(!mentions("Old Tom", "the"));
+        let names = [String::from("Golden Key"), String::from("Silver Key"), String::from("Key")];
+        assert_eq!(list_choices(&names), "the Golden Key, the Silver Key or the Key");
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{console::Console, game::State, parser::known_verbs};
+use super::{all_hold, Room, World};
+
+// Feedback for commands nobody understood.
+// Each word is compared against the verbs the game knows (including any the room's triggers
+// listen for) and the names of everything the player can currently see or is carrying. Words
+// that are a typo or two away from one of those are corrected, and the corrected command is
+// offered as a suggestion. The same vocabulary is what tab completion offers at the prompt.
+
+// Edit distance counting insertions, deletions, substitutions and swapped neighbours.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// The closest of `known` to `word`, if it's near enough to plausibly be a typo.
+// Single letters are left out on both sides: any one character is a typo away from `n`.
+fn closest<'a>(word: &str, known: &'a [String]) -> Option<&'a String> {
+    if word.chars().count() < 2 {
+        return None;
+    }
+    let limit = if word.chars().count() <= 4 { 1 } else { 2 };
+    known.iter()
+        .filter(|k| k.chars().count() >= 2)
+        .map(|k| (distance(word, k), k))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+fn words_of(name: &str) -> impl Iterator<Item = String> + '_ {
+    name.split_whitespace().map(str::to_lowercase)
+}
+
+impl World {
+    // What's worth offering for tab completion in the player's current situation: the verbs
+    // and directions, and the names of whatever they can see or are carrying.
+    pub fn completions(&self, state: &State) -> (Vec<String>, Vec<String>) {
+        let State { room: id, inv, flags, contents, .. } = state;
+        let room = &self.rooms[id];
+        let reachable = self.around(id, contents, flags).into_iter().chain(self.with_contents(&inv.items, contents, flags));
+        let mut verbs: Vec<String> = known_verbs().map(String::from).collect();
+        verbs.extend(room.triggers.iter().flat_map(|t| &t.patterns).map(|p| p.verb.clone()));
+        let mut words = vec![];
+        for item in reachable.map(|id| self.item(&id)) {
+            words.push(item.name.to_lowercase());
+            words.extend(item.aliases.iter().map(|a| a.to_lowercase()));
+        }
+        words.extend(room.things.iter().filter(|t| all_hold(&t.conds, inv, flags)).map(|t| t.name.to_lowercase()));
+        words.extend(room.npcs.iter().map(|&i| self.npcs[i].name.to_lowercase()));
+        (verbs, words)
+    }
+
+    // Tell the player `text` wasn't understood, suggesting what they might have meant.
+    pub(super) fn not_understood(&self, text: &str, room: &Room, reachable: &[String], console: &mut dyn Console) {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            return;
+        }
+
+        let mut verbs: Vec<String> = known_verbs().map(String::from).collect();
+        verbs.extend(room.triggers.iter().flat_map(|t| &t.patterns).map(|p| p.verb.clone()));
+
+        let mut nouns: Vec<String> = vec![];
+        for item in reachable.iter().map(|id| self.item(id)) {
+            nouns.extend(words_of(&item.name));
+            nouns.extend(item.aliases.iter().flat_map(|a| words_of(a)));
+        }
+        nouns.extend(room.things.iter().flat_map(|t| words_of(&t.name)));
+        nouns.extend(room.npcs.iter().flat_map(|&i| words_of(&self.npcs[i].name)));
+
+        // A word that starts several verbs is ambiguous rather than misspelt.
+        if !verbs.iter().any(|v| v == words[0]) {
+            let mut starts: Vec<&str> = verbs.iter().filter(|v| v.starts_with(words[0])).map(String::as_str).collect();
+            starts.sort();
+            starts.dedup();
+            if starts.len() > 1 {
+                let (last, rest) = starts.split_last().unwrap();
+                console.println(&format!("\"{}\" could mean {} or {}.", words[0], rest.join(", "), last));
+                return;
+            }
+        }
+
+        let mut corrected: Vec<&str> = vec![];
+        for (i, word) in words.iter().enumerate() {
+            let known = if i == 0 { &verbs } else { &nouns };
+            match known.iter().find(|k| k == word).or_else(|| closest(word, known)) {
+                Some(k) => corrected.push(k),
+                None => corrected.push(word),
+            }
+        }
+
+        if corrected != words {
+            console.println(&format!("I don't understand \"{}\". Did you mean \"{}\"?", text, corrected.join(" ")));
+        } else if !verbs.iter().any(|v| v == words[0]) {
+            console.println(&format!("I don't know how to \"{}\". Type `help` for a list of commands.", words[0]));
+        } else {
+            console.println(&format!("I don't understand \"{}\".", text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{closest, distance};
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(distance("north", "north"), 0);
+        assert_eq!(distance("nroth", "north"), 1);
+        assert_eq!(distance("nrth", "north"), 1);
+        assert_eq!(distance("lamp", "lump"), 1);
+        assert_eq!(distance("", "key"), 3);
+    }
+
+    #[test]
+    fn close_enough_to_suggest() {
+        let known: Vec<String> = ["north", "look", "inventory"].iter().map(|k| String::from(*k)).collect();
+        assert_eq!(closest("lokk", &known), Some(&known[1]));
+        assert_eq!(closest("invetnory", &known), Some(&known[2]));
+        assert_eq!(closest("lkko", &known), None);
+        let known: Vec<String> = ["n", "x", "go"].iter().map(|k| String::from(*k)).collect();
+        assert_eq!(closest("0", &known), None);
+        assert_eq!(closest("xx", &known), None);
+        assert_eq!(closest("gp", &known), Some(&known[2]));
+    }
+}
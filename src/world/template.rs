@@ -0,0 +1,156 @@
+use crate::{Flags, Inventory};
+use super::{all_hold, parse_conds, Cond, Refs, Scope};
+
+// Room text templates.
+// A line of room text can switch between alternatives and fill in variables as it's shown:
+//
+//   {if <conditions>}...{else}...{end}   either branch, depending on the conditions
+//   {if first}...{else}...{end}          the first branch only on the player's first visit
+//   {<variable>}                         the variable's current value
+//
+// Blocks nest, and `{else}` is optional. Conditions are the same as everywhere else in the
+// world file, with `has:<item>` accepted as well as `has <item>`. Everything is checked when
+// the world is loaded, down to the items and variables named, so a template that renders at
+// all renders correctly.
+
+enum Test {
+    Conds(Vec<Cond>),
+    FirstVisit,
+}
+
+enum Part {
+    Text(String),
+    Var(String),
+    If(Test, Vec<Part>, Vec<Part>),
+}
+
+pub(super) struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub(super) fn parse(s: &str, n: usize, refs: &mut Refs, scope: &Scope) -> Result<Template, String> {
+        let mut rest = s;
+        let (parts, end) = parse_parts(&mut rest, n, refs, scope)?;
+        match end {
+            None => Ok(Template { parts }),
+            Some(tag) => Err(format!("`{{{}}}` without a matching `{{if}}`", tag)),
+        }
+    }
+
+    // The text as it reads right now. `first` says whether this is the player's first visit.
+    pub(super) fn render(&self, inv: &Inventory, flags: &Flags, first: bool) -> String {
+        let mut out = String::new();
+        render_parts(&self.parts, inv, flags, first, &mut out);
+        out
+    }
+}
+
+// Parse parts until the text runs out or an `{else}` or `{end}` is reached, which is returned
+// so the caller can tell which it was.
+fn parse_parts<'a>(rest: &mut &'a str, n: usize, refs: &mut Refs, scope: &Scope) -> Result<(Vec<Part>, Option<&'a str>), String> {
+    let mut parts = vec![];
+    loop {
+        let Some((text, after)) = rest.split_once('{') else {
+            if !rest.is_empty() {
+                parts.push(Part::Text(String::from(*rest)));
+            }
+            *rest = "";
+            return Ok((parts, None));
+        };
+        if !text.is_empty() {
+            parts.push(Part::Text(String::from(text)));
+        }
+        let (tag, after) = after.split_once('}').ok_or_else(|| String::from("`{` without a closing `}`"))?;
+        *rest = after;
+
+        let tag = tag.trim();
+        match tag.split_once(char::is_whitespace).unwrap_or((tag, "")) {
+            ("else" | "end", "") => return Ok((parts, Some(tag))),
+            ("if", conds) => {
+                let test = match conds.trim() {
+                    "first" => Test::FirstVisit,
+                    "" => return Err(String::from("`{if}` needs a condition")),
+                    conds => Test::Conds(parse_conds(conds, n, refs, scope)?),
+                };
+                let (then, end) = parse_parts(rest, n, refs, scope)?;
+                let otherwise = match end {
+                    Some("else") => match parse_parts(rest, n, refs, scope)? {
+                        (otherwise, Some("end")) => otherwise,
+                        _ => return Err(String::from("`{else}` without a matching `{end}`")),
+                    },
+                    Some(_) => vec![],
+                    None => return Err(String::from("`{if}` without a matching `{end}`")),
+                };
+                parts.push(Part::If(test, then, otherwise));
+            }
+            (var, "") if !var.is_empty() => parts.push(Part::Var(scope.resolve(var)?)),
+            _ => return Err(format!("can't make sense of `{{{}}}`", tag)),
+        }
+    }
+}
+
+fn render_parts(parts: &[Part], inv: &Inventory, flags: &Flags, first: bool, out: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Var(var) => if let Some(val) = flags.get(var) {
+                out.push_str(&val.to_string());
+            },
+            Part::If(test, then, otherwise) => {
+                let holds = match test {
+                    Test::Conds(conds) => all_hold(conds, inv, flags),
+                    Test::FirstVisit => first,
+                };
+                render_parts(if holds { then } else { otherwise }, inv, flags, first, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::{Flags, Inventory, Value};
+    use super::{Refs, Scope, Template};
+
+    fn parse(s: &str) -> Result<Template, String> {
+        let vars = HashMap::from([
+            (String::from("lamp_lit"), Value::Bool(false)),
+            (String::from("room_a.gold"), Value::Int(3)),
+        ]);
+        let mut refs = Refs::default();
+        Template::parse(s, 1, &mut refs, &Scope { vars: &vars, ns: Some("room_a") })
+    }
+
+    #[test]
+    fn renders_conditions_and_variables() {
+        let template = parse("{if first}New.{else}Again.{end} {if lamp_lit}Bright{else}Dark{if has:key}, but you have a key{end}{end}. {gold} gold.").unwrap();
+        let mut inv = Inventory::new();
+        let mut flags = Flags::new();
+        flags.set_int("room_a.gold", 3);
+        assert_eq!(template.render(&inv, &flags, true), "New. Dark. 3 gold.");
+        inv.add("key");
+        flags.set_as("lamp_lit", false);
+        assert_eq!(template.render(&inv, &flags, false), "Again. Dark, but you have a key. 3 gold.");
+        flags.set_as("lamp_lit", true);
+        assert_eq!(template.render(&inv, &flags, false), "Again. Bright. 3 gold.");
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(parse("{if lamp_lit}unfinished").is_err());
+        assert!(parse("stray {end}").is_err());
+        assert!(parse("{if}empty{end}").is_err());
+        assert!(parse("{unknown_var}").is_err());
+        assert!(parse("open {brace").is_err());
+    }
+
+    #[test]
+    fn items_are_left_for_the_world_to_check() {
+        let vars = HashMap::new();
+        let mut refs = Refs::default();
+        Template::parse("{if has:Golden Key}x{end}", 7, &mut refs, &Scope { vars: &vars, ns: None }).unwrap();
+        assert_eq!(refs.items, [(7, String::from("Golden Key"))]);
+    }
+}